@@ -7,22 +7,19 @@
 use crate::pi::UART_CONSOLE;
 use core::ops::Range;
 
+#[macro_use]
 mod pi;
 mod panic_wait;
 mod arch;
 mod runtime_init;
-mod memory;
 mod shell;
-mod xmodem;
 
-#[macro_use]
-mod console;
-// mod xmodem;
+use pios::{memory, xmodem};
 
 
 fn kernel_init() -> ! {
 
-    use xmodem::{ModemError, ErrorKind};
+    use pios::io::ErrorKind;
 
     // Must initialize the UART device before we can print to the console
     pi::UART_CONSOLE.init();
@@ -35,10 +32,12 @@ fn kernel_init() -> ! {
     // Boot Core Stack End is same address as bootloader start address & end of memory space for Kernel
     let kernel_end: *mut u8 = pi::memory::map::BOOT_CORE_STACK_END as *mut u8;
     let kernel_range = kernel_addr..kernel_end;
-    let wrapped_kernel_memory = memory::MemCursorWriteOnly::new(kernel_range);
 
     loop {
-        match xmodem::Xmodem::receive(pi::UART_CONSOLE, wrapped_kernel_memory) {
+        let mut wrapped_kernel_memory = unsafe { memory::MemWriter::new(kernel_range.clone()) };
+        // ttywrite only answers NAK, it gives up on the 'C' a CRC receiver opens with
+        let mut receiver = xmodem::Xmodem::new_with_mode(&pi::UART_CONSOLE, xmodem::Mode::Checksum);
+        match receiver.receive_into(&mut wrapped_kernel_memory) {
             Ok(_) => {
                 // This interprets a memory address as a function I can call from rust to jump to the new kernel
                 // TODO: Check the rust calling convention to ensure this doesn't introduce any weird errors with CPU registers
@@ -47,6 +46,7 @@ fn kernel_init() -> ! {
                 kernel()
             }
             Err(err) => match err.kind() {
+                ErrorKind::CapacityExceeded => kprintln!("Kernel too big, it has to end before {:?}", kernel_end),
                 _ => kprintln!("Error: {:?}", err)
             }
         }                
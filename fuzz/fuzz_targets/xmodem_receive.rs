@@ -0,0 +1,62 @@
+// Throws whatever the fuzzer comes up with at the XMODEM receiver as if a sender had sent it.
+// Once the input runs out the line goes quiet, so every run ends in a timeout at worst.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pios::duration::Duration;
+use pios::io;
+use pios::xmodem::{Mode, RetryPolicy, Xmodem};
+
+struct Line<'a> {
+    data: &'a [u8],
+}
+
+impl io::Read for Line<'_> {
+    /// A byte at a time, to go through every path a slow line would
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match (self.data.split_first(), buf.first_mut()) {
+            (_, None) => Ok(0),
+            (Some((&byte, rest)), Some(first)) => {
+                *first = byte;
+                self.data = rest;
+                Ok(1)
+            }
+            (None, _) => Err(io::Error::new(io::ErrorKind::TimedOut)),
+        }
+    }
+}
+
+// Our replies go nowhere, the input doesn't depend on them
+impl io::Write for Line<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // First byte picks the mode so both get covered
+    let (mode, data) = match data.split_first() {
+        Some((&first, rest)) if first & 1 == 1 => (Mode::Checksum, rest),
+        Some((_, rest)) => (Mode::Crc16, rest),
+        None => return,
+    };
+
+    // Timeouts never actually get waited for, only the counts matter
+    let policy = RetryPolicy {
+        max_retries: 4,
+        start_attempts: 4,
+        crc_attempts: 2,
+        start_interval: Duration::ZERO,
+        block_timeout: Duration::ZERO,
+        byte_timeout: Duration::ZERO,
+    };
+
+    let mut receiver = Xmodem::new_with_mode(Line { data }, mode);
+    receiver.set_retry_policy(policy);
+    let _ = receiver.receive_into(io::Sink);
+});
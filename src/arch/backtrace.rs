@@ -0,0 +1,77 @@
+// Stack walking using the AArch64 frame records, needs the kernel built with
+// -C force-frame-pointers=yes (the makefile does this).
+//
+// Every function that sets up a frame pushes x29 (the caller's frame pointer) and x30 (the
+// return address) as a pair and points x29 at them, so the frame pointers form a linked list
+// back up the stack.
+
+use crate::pi::memory;
+use core::arch::asm;
+
+/// Deepest we'll walk, in case the frame records are junk
+const MAX_FRAMES: usize = 64;
+
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let fp: u64;
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) }
+    fp
+}
+
+#[inline(always)]
+pub fn link_register() -> u64 {
+    let lr: u64;
+    unsafe { asm!("mov {}, x30", out(reg) lr, options(nomem, nostack)) }
+    lr
+}
+
+#[inline(always)]
+pub fn stack_pointer() -> u64 {
+    let sp: u64;
+    unsafe { asm!("mov {}, sp", out(reg) sp, options(nomem, nostack)) }
+    sp
+}
+
+/// Return addresses of each frame up the stack, starting from the frame record at `fp`
+pub struct Backtrace {
+    fp: u64,
+    depth: usize,
+}
+
+impl Backtrace {
+    /// Walk from the caller's frame
+    #[inline(always)]
+    pub fn new() -> Backtrace {
+        Backtrace::from_frame_pointer(frame_pointer())
+    }
+
+    /// Walk from an arbitrary frame, e.g. the x29 saved in an exception context
+    pub fn from_frame_pointer(fp: u64) -> Backtrace {
+        Backtrace { fp, depth: 0 }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        // Frame records are 16 byte aligned and live on the stack, anything else means we've
+        // hit the top or the chain is corrupt
+        let in_stack = self.fp != 0
+            && self.fp % 16 == 0
+            && self.fp < memory::boot_core_stack_end() as u64;
+
+        if !in_stack || self.depth >= MAX_FRAMES {
+            return None;
+        }
+
+        let record = self.fp as *const u64;
+        let (next_fp, return_address) = unsafe { (record.read_volatile(), record.add(1).read_volatile()) };
+
+        // The stack grows down so callers' frames must be at higher addresses
+        self.fp = if next_fp > self.fp { next_fp } else { 0 };
+        self.depth += 1;
+
+        if return_address == 0 { None } else { Some(return_address) }
+    }
+}
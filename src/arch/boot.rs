@@ -1,4 +1,5 @@
 use crate::pi::cpu::BOOT_CORE_ID;
+use core::arch::{asm, global_asm};
 use cortex_a::{asm, registers::*};
 use tock_registers::interfaces::Writeable;
 
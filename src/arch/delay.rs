@@ -0,0 +1,124 @@
+// Busy-wait delays that actually mean something in real time.
+//
+// Normally these count off the generic timer's system counter, waiting in WFE for anything
+// longer than a few event stream periods so the core isn't flat out the whole time. The event
+// stream (CNTKCTL_EL1.EVNTEN) wakes WFE every time a chosen counter bit flips, so we never
+// oversleep by more than one period.
+//
+// If the counter isn't usable (CNTFRQ never got set up, or it isn't ticking) we fall back on a
+// nop loop timed against the system timer at boot.
+
+use crate::{arch::timer, pi::drivers::timer::SYSTEM_TIMER, syncro::{Lockable, NoLock}, time::Duration};
+use core::arch::asm;
+use cortex_a::asm;
+
+/// Aim for an event stream wakeup at least every this many nanoseconds
+const EVENT_PERIOD_NS: u64 = 10_000;
+
+/// Nop loops run when calibrating, takes well under a millisecond at 1.5GHz
+const CALIBRATION_LOOPS: u64 = 100_000;
+
+// CNTKCTL_EL1 fields, cortex-a doesn't have the register
+const EVNTEN: u64 = 1 << 2;
+const EVNTDIR: u64 = 1 << 3;
+const EVNTI_SHIFT: u64 = 4;
+const EVNTI_MASK: u64 = 0b1111 << EVNTI_SHIFT;
+
+struct Calibration {
+    /// Counter is running & CNTFRQ is set
+    counter: bool,
+    /// Counter ticks between event stream wakeups, 0 if the event stream is off
+    event_period: u64,
+    /// Nop loops per microsecond, only used without a counter. Starts as a guess for 1.5GHz
+    /// and one loop a cycle, which errs on the long side.
+    loops_per_us: u64,
+}
+
+static CALIBRATION: NoLock<Calibration> = NoLock::new(Calibration {
+    counter: true,
+    event_period: 0,
+    loops_per_us: 1500,
+});
+
+/// Pick a clock source and calibrate it, run once per core before relying on the delays
+///
+/// The event stream is per core so secondary cores have to call this too.
+pub fn init() {
+    let frequency = timer::frequency();
+    let counter = frequency != 0 && {
+        let start = timer::counter();
+        spin_loops(1000);
+        timer::counter() != start
+    };
+
+    let event_period = if counter { enable_event_stream(frequency) } else { 0 };
+    let loops_per_us = calibrate_loops();
+
+    CALIBRATION.lock(|calibration| {
+        calibration.counter = counter;
+        calibration.event_period = event_period;
+        calibration.loops_per_us = loops_per_us;
+    });
+}
+
+/// Turn on the event stream at the first counter bit slow enough for `EVENT_PERIOD_NS`,
+/// returns the period in counter ticks
+fn enable_event_stream(frequency: u64) -> u64 {
+    let wanted = (EVENT_PERIOD_NS as u128 * frequency as u128 / 1_000_000_000) as u64;
+
+    // An event fires each time bit n goes 0 -> 1, every 2^(n + 1) ticks
+    let mut bit = 0;
+    while bit < 15 && (2 << bit) < wanted {
+        bit += 1;
+    }
+
+    let mut cntkctl: u64;
+    unsafe { asm!("mrs {}, CNTKCTL_EL1", out(reg) cntkctl, options(nostack)) }
+    cntkctl &= !(EVNTI_MASK | EVNTDIR);
+    cntkctl |= EVNTEN | (bit << EVNTI_SHIFT);
+    unsafe { asm!("msr CNTKCTL_EL1, {}", in(reg) cntkctl, options(nostack)) }
+
+    2 << bit
+}
+
+/// Time a run of nop loops against the system timer
+fn calibrate_loops() -> u64 {
+    let start = SYSTEM_TIMER.read();
+    spin_loops(CALIBRATION_LOOPS);
+    let elapsed = SYSTEM_TIMER.read() - start;
+
+    // Round up, a delay that's a bit long is better than one that's short
+    ((CALIBRATION_LOOPS + elapsed - 1) / elapsed.max(1)).max(1)
+}
+
+#[inline(always)]
+fn spin_loops(n: u64) {
+    for _ in 0..n {
+        asm::nop();
+    }
+}
+
+/// Wait at least `duration`
+pub fn delay(duration: Duration) {
+    let (counter, event_period, loops_per_us) =
+        CALIBRATION.lock(|calibration| (calibration.counter, calibration.event_period, calibration.loops_per_us));
+
+    if !counter {
+        let us = (duration.as_nanos() + 999) / 1000;
+        spin_loops(us.saturating_mul(loops_per_us));
+        return;
+    }
+
+    let deadline = timer::counter().saturating_add(timer::duration_to_ticks(duration));
+    loop {
+        let now = timer::counter();
+        if now >= deadline {
+            break;
+        }
+
+        // Sleep while there's at least a whole event period to go, spin the last bit
+        if event_period != 0 && deadline - now > event_period {
+            asm::wfe();
+        }
+    }
+}
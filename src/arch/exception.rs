@@ -1,5 +1,10 @@
-use cortex_a::registers::*;
-use tock_registers::interfaces::Readable;
+use super::backtrace::Backtrace;
+use crate::ksyms::Symbolize;
+use core::{arch::{asm, global_asm}, cell::UnsafeCell, fmt};
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::{Readable, Writeable};
+
+global_asm!(include_str!("exception.s"));
 
 /// The processing element's current privilege level.
 pub fn current_privilege_level() -> &'static str {
@@ -10,4 +15,163 @@ pub fn current_privilege_level() -> &'static str {
         Some(CurrentEL::EL::Value::EL0) => "EL0",
         _ => "Unknown",
     }
-}
\ No newline at end of file
+}
+
+/// Everything exception.s saves on the stack before calling into rust
+#[repr(C)]
+pub struct ExceptionContext {
+    /// General purpose registers x0 - x29
+    gpr: [u64; 30],
+
+    /// Link register, aka x30
+    lr: u64,
+
+    /// Exception link register, the PC the exception was taken from
+    elr_el1: u64,
+
+    /// Saved program status
+    spsr_el1: u64,
+
+    /// Exception syndrome register
+    esr_el1: u64,
+}
+
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ESR_EL1:  {:#018x} (EC: {:#04x})", self.esr_el1, self.esr_el1 >> 26)?;
+        writeln!(f, "ELR_EL1:  {}", Symbolize(self.elr_el1))?;
+        writeln!(f, "SPSR_EL1: {:#018x}", self.spsr_el1)?;
+        writeln!(f, "General purpose registers:")?;
+        for (i, reg) in self.gpr.iter().enumerate() {
+            write!(f, "      x{: <2}: {: >#018x}", i, reg)?;
+            if i % 2 == 1 {
+                writeln!(f)?;
+            }
+        }
+        writeln!(f, "      lr : {}", Symbolize(self.lr))?;
+
+        // Walk the stack of whatever was interrupted, not ours
+        write!(f, "Backtrace:")?;
+        for (i, address) in Backtrace::from_frame_pointer(self.gpr[29]).enumerate() {
+            write!(f, "\n  {: >2}: {}", i, Symbolize(address))?;
+        }
+        Ok(())
+    }
+}
+
+/// Anything we don't know how to handle ends up here
+fn default_exception_handler(e: &ExceptionContext) {
+    panic!("Unexpected CPU exception\n\n{}", e);
+}
+
+// The rust side of the vector table in exception.s
+//
+// The kernel runs at EL1 using SP_EL1 so only the current_elx handlers should ever be called
+// for now, everything else is a bug.
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+    crate::pi::irq::handle_irq();
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+/// Point VBAR_EL1 at the vector table in exception.s
+///
+/// ## Safety
+///
+/// Must be called on each core before interrupts are unmasked on it
+pub unsafe fn handling_init() {
+    extern "Rust" {
+        static __exception_vector_start: UnsafeCell<()>;
+    }
+
+    VBAR_EL1.set(__exception_vector_start.get() as u64);
+
+    // Make sure the vector base is in place before anything can trap
+    barrier::isb(barrier::SY);
+}
+
+/// Unmask IRQs on the calling core
+#[inline(always)]
+pub fn local_irq_unmask() {
+    // DAIFClr/DAIFSet only touch the bits we ask for, bit 1 is I
+    unsafe { asm!("msr DAIFClr, #2", options(nostack)) }
+}
+
+/// Mask IRQs on the calling core
+#[inline(always)]
+pub fn local_irq_mask() {
+    unsafe { asm!("msr DAIFSet, #2", options(nostack)) }
+}
+
+/// True if IRQs are masked on the calling core
+#[inline(always)]
+pub fn is_local_irq_masked() -> bool {
+    DAIF.is_set(DAIF::I)
+}
+
+/// Run `f` with IRQs masked on the calling core, putting the mask back how it was afterwards
+#[inline(always)]
+pub fn exec_with_irq_masked<R>(f: impl FnOnce() -> R) -> R {
+    let saved = DAIF.get();
+    local_irq_mask();
+
+    let ret = f();
+
+    DAIF.set(saved);
+    ret
+}
@@ -1,4 +1,7 @@
 pub mod cpu;
 pub mod boot;
 pub mod smp;
-pub mod exception;
\ No newline at end of file
+pub mod exception;
+pub mod timer;
+pub mod delay;
+pub mod backtrace;
\ No newline at end of file
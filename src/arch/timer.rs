@@ -0,0 +1,152 @@
+// ARM generic timer, every core has its own physical timer counting off the shared system
+// counter, so it's the obvious source for a per-core scheduler tick and a high resolution
+// clock that doesn't need an MMIO read.
+//
+// prepare_el2_to_el1 already lets EL1 at CNTPCT_EL0 & CNTP_*_EL0. The timer interrupt is a
+// PPI, same ID on every core but banked, so each core enables its own.
+
+use crate::{arch::{exception, smp}, pi::irq, syncro::{Lockable, NoLock}, time::Duration};
+use core::arch::asm;
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::{Readable, Writeable};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+const CORES: usize = 4;
+
+/// Called from the timer interrupt on the core whose tick it is
+pub type TickHandler = fn();
+
+#[derive(Clone, Copy)]
+struct Tick {
+    handler: TickHandler,
+    /// Period in counter ticks, 0 for a one-shot
+    period: u64,
+}
+
+static TICKS: NoLock<[Option<Tick>; CORES]> = NoLock::new([None; CORES]);
+
+/// Counter frequency in Hz, set by the firmware, 54MHz on the Pi 4
+#[inline(always)]
+pub fn frequency() -> u64 {
+    CNTFRQ_EL0.get()
+}
+
+/// Current value of the system counter
+#[inline(always)]
+pub fn counter() -> u64 {
+    // Without the isb the read can be hoisted above whatever we're trying to time
+    unsafe { barrier::isb(barrier::SY) };
+    CNTPCT_EL0.get()
+}
+
+/// Time since the counter started, which is as close to power on as we can get
+pub fn uptime() -> Duration {
+    ticks_to_duration(counter())
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos((ticks as u128 * NANOS_PER_SEC / frequency() as u128) as u64)
+}
+
+/// Rounds up so a timeout is never shorter than asked for
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos() as u128 * frequency() as u128;
+    ((nanos + NANOS_PER_SEC - 1) / NANOS_PER_SEC) as u64
+}
+
+/// Fire the timer interrupt once the counter reaches `ticks`
+pub fn set_compare(ticks: u64) {
+    // cortex-a doesn't know about CNTP_CVAL_EL0
+    unsafe { asm!("msr CNTP_CVAL_EL0, {}", in(reg) ticks, options(nostack)) }
+}
+
+/// Compare value the timer is waiting for
+pub fn compare() -> u64 {
+    let ticks: u64;
+    unsafe { asm!("mrs {}, CNTP_CVAL_EL0", out(reg) ticks, options(nostack)) }
+    ticks
+}
+
+/// Fire the timer interrupt `ticks` from now, only 32 bits of it count
+pub fn set_countdown(ticks: u32) {
+    CNTP_TVAL_EL0.set(ticks as u64);
+}
+
+pub fn enable() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+pub fn disable() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+}
+
+/// True if the counter has passed the compare value, whether or not the IRQ is masked
+pub fn is_pending() -> bool {
+    CNTP_CTL_EL0.is_set(CNTP_CTL_EL0::ISTATUS)
+}
+
+/// Call `handler` on this core every `period` until `stop_tick`
+///
+/// Replaces any tick already running on this core. The timer interrupt is only enabled for
+/// the calling core, other cores have to start their own.
+pub fn start_tick(period: Duration, handler: TickHandler) {
+    let period = duration_to_ticks(period).max(1);
+    arm(period, period, handler);
+}
+
+/// Call `handler` on this core once, after `delay`
+pub fn oneshot(delay: Duration, handler: TickHandler) {
+    arm(duration_to_ticks(delay), 0, handler);
+}
+
+fn arm(first: u64, period: u64, handler: TickHandler) {
+    let core: usize = smp::core_id();
+
+    // The tick interrupt reads the table, keep it out until the entry & compare agree
+    exception::exec_with_irq_masked(|| {
+        TICKS.lock(|ticks| ticks[core] = Some(Tick { handler, period }));
+        set_compare(counter() + first);
+        enable();
+    });
+    irq::register_handler(irq::GENERIC_TIMER, handle_tick);
+}
+
+/// Stop this core's tick, the handler won't be called again
+pub fn stop_tick() {
+    let core: usize = smp::core_id();
+
+    exception::exec_with_irq_masked(|| {
+        disable();
+        TICKS.lock(|ticks| ticks[core] = None);
+    });
+}
+
+fn handle_tick() {
+    let core: usize = smp::core_id();
+
+    let handler = TICKS.lock(|ticks| {
+        let tick = ticks[core]?;
+
+        if tick.period == 0 {
+            ticks[core] = None;
+            disable();
+        } else {
+            // Step from the last compare so the tick doesn't drift, unless we've fallen so
+            // far behind the next one has already gone
+            let now = counter();
+            let mut next = compare() + tick.period;
+            if next <= now {
+                next = now + tick.period;
+            }
+            set_compare(next);
+        }
+
+        Some(tick.handler)
+    });
+
+    match handler {
+        Some(handler) => handler(),
+        // Nothing wants it on this core, the line is level triggered so it has to be quietened
+        None => disable(),
+    }
+}
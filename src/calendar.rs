@@ -0,0 +1,256 @@
+// Calendar arithmetic for the wall clock: converting between Unix time and UTC dates, parsing
+// and formatting them. Pure date maths so it builds and gets tested on the host, the kernel's
+// wallclock module keeps the actual clock.
+
+use core::fmt;
+use crate::duration::Duration;
+
+const SECS_PER_MINUTE: u64 = 60;
+const SECS_PER_HOUR: u64 = 60 * SECS_PER_MINUTE;
+const SECS_PER_DAY: u64 = 24 * SECS_PER_HOUR;
+
+/// A UTC calendar date & time
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanos: u32,
+}
+
+impl DateTime {
+    /// Convert a time since 1970-01-01T00:00:00Z
+    pub fn from_unix(time: Duration) -> DateTime {
+        let secs = time.as_secs();
+        let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+        let secs_of_day = secs % SECS_PER_DAY;
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (secs_of_day / SECS_PER_HOUR) as u8,
+            minute: (secs_of_day % SECS_PER_HOUR / SECS_PER_MINUTE) as u8,
+            second: (secs_of_day % SECS_PER_MINUTE) as u8,
+            nanos: time.subsec_nanos(),
+        }
+    }
+
+    /// Time since 1970-01-01T00:00:00Z
+    pub fn to_unix(&self) -> Duration {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = days * SECS_PER_DAY
+            + self.hour as u64 * SECS_PER_HOUR
+            + self.minute as u64 * SECS_PER_MINUTE
+            + self.second as u64;
+
+        Duration::from_secs(secs).saturating_add(Duration::from_nanos(self.nanos as u64))
+    }
+
+    /// Parse `YYYY-MM-DDTHH:MM:SS`, with an optional trailing `Z`. A space works in place of the
+    /// `T` too. Returns `None` for anything else or a date that doesn't exist.
+    pub fn parse(s: &str) -> Option<DateTime> {
+        let s = s.strip_suffix('Z').unwrap_or(s).as_bytes();
+        if s.len() != 19 || s[4] != b'-' || s[7] != b'-' || !matches!(s[10], b'T' | b' ')
+            || s[13] != b':' || s[16] != b':'
+        {
+            return None;
+        }
+
+        let datetime = DateTime {
+            year: digits(&s[0..4])?,
+            month: digits(&s[5..7])? as u8,
+            day: digits(&s[8..10])? as u8,
+            hour: digits(&s[11..13])? as u8,
+            minute: digits(&s[14..16])? as u8,
+            second: digits(&s[17..19])? as u8,
+            nanos: 0,
+        };
+
+        let valid = datetime.year >= 1970
+            && (1..=12).contains(&datetime.month)
+            && datetime.day >= 1
+            && datetime.day <= days_in_month(datetime.year, datetime.month)
+            && datetime.hour < 24
+            && datetime.minute < 60
+            && datetime.second < 60;
+
+        if valid { Some(datetime) } else { None }
+    }
+}
+
+/// ISO-8601, e.g. `2021-06-01T12:00:00Z`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Formats a duration the way `uptime` does, e.g. `3 days, 04:05:06`
+pub struct Uptime(pub Duration);
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let days = secs / SECS_PER_DAY;
+        let secs_of_day = secs % SECS_PER_DAY;
+
+        match days {
+            0 => {},
+            1 => write!(f, "1 day, ")?,
+            _ => write!(f, "{} days, ", days)?,
+        }
+
+        write!(f, "{:02}:{:02}:{:02}",
+            secs_of_day / SECS_PER_HOUR, secs_of_day % SECS_PER_HOUR / SECS_PER_MINUTE, secs_of_day % SECS_PER_MINUTE)
+    }
+}
+
+fn digits(s: &[u8]) -> Option<u32> {
+    s.iter().try_fold(0u32, |acc, &byte| match byte {
+        b'0'..=b'9' => Some(acc * 10 + (byte - b'0') as u32),
+        _ => None,
+    })
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Calendar conversions from Howard Hinnant's date algorithms, with the era shifted so the
+// arithmetic stays unsigned for anything after 1970
+// http://howardhinnant.github.io/date_algorithms.html
+
+/// Days since 1970-01-01 to year/month/day
+fn civil_from_days(days: u64) -> (u32, u8, u8) {
+    // Count from 0000-03-01 so the leap day is the last day of the year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u8;
+    let year = (year_of_era + era * 400) as u32 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Year/month/day to days since 1970-01-01
+fn days_from_civil(year: u32, month: u8, day: u8) -> u64 {
+    let year = year as u64 - if month <= 2 { 1 } else { 0 };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as u64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u32, month: u8, day: u8) -> DateTime {
+        DateTime { year, month, day, hour: 0, minute: 0, second: 0, nanos: 0 }
+    }
+
+    #[test]
+    fn days_roundtrip() {
+        let cases = [
+            (0, (1970, 1, 1)),
+            (1, (1970, 1, 2)),
+            (365, (1971, 1, 1)),
+            // 2000 is a leap year, 1900 and 2100 aren't
+            (11_016, (2000, 2, 29)),
+            (11_017, (2000, 3, 1)),
+            (19_417, (2023, 3, 1)),
+            (47_540, (2100, 2, 28)),
+            (47_541, (2100, 3, 1)),
+            (2_932_896, (9999, 12, 31)),
+        ];
+
+        for &(days, (year, month, day)) in &cases {
+            assert_eq!(civil_from_days(days), (year, month, day), "day {}", days);
+            assert_eq!(days_from_civil(year, month, day), days, "{}-{}-{}", year, month, day);
+        }
+
+        // Every day for a few centuries goes there and back
+        let mut previous = civil_from_days(0);
+        for days in 1..200_000 {
+            let civil = civil_from_days(days);
+            assert!(civil > previous);
+            assert_eq!(days_from_civil(civil.0, civil.1, civil.2), days);
+            previous = civil;
+        }
+    }
+
+    #[test]
+    fn unix_roundtrip() {
+        let time = Duration::from_secs(951_782_400 + 23 * 3600 + 59 * 60 + 59) + Duration::from_nanos(5);
+        let datetime = DateTime::from_unix(time);
+        assert_eq!(datetime, DateTime { hour: 23, minute: 59, second: 59, nanos: 5, ..date(2000, 2, 29) });
+        assert_eq!(datetime.to_unix(), time);
+        assert_eq!(DateTime::from_unix(Duration::ZERO), date(1970, 1, 1));
+    }
+
+    #[test]
+    fn parse() {
+        let expected = DateTime { hour: 12, minute: 34, second: 56, ..date(2024, 2, 29) };
+        assert_eq!(DateTime::parse("2024-02-29T12:34:56Z"), Some(expected));
+        assert_eq!(DateTime::parse("2024-02-29T12:34:56"), Some(expected));
+        assert_eq!(DateTime::parse("2024-02-29 12:34:56"), Some(expected));
+        assert_eq!(DateTime::parse("1970-01-01T00:00:00Z"), Some(date(1970, 1, 1)));
+        assert_eq!(format!("{}", expected), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn parse_rejects() {
+        for s in &[
+            "",
+            "2024-02-29",
+            "2024-02-29T12:34",
+            "2024-02-29T12:34:56ZZ",
+            "2024/02/29T12:34:56",
+            "2024-02-29X12:34:56",
+            "2024-2-29T12:34:56Z",
+            "2024-02-29T12:34:5a",
+            "+024-02-29T12:34:56",
+            "1969-12-31T23:59:59",
+            "2023-02-29T00:00:00",
+            "2100-02-29T00:00:00",
+            "2024-00-01T00:00:00",
+            "2024-13-01T00:00:00",
+            "2024-04-31T00:00:00",
+            "2024-01-00T00:00:00",
+            "2024-01-01T24:00:00",
+            "2024-01-01T00:60:00",
+            "2024-01-01T00:00:60",
+        ] {
+            assert_eq!(DateTime::parse(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn uptime() {
+        assert_eq!(format!("{}", Uptime(Duration::from_secs(59))), "00:00:59");
+        assert_eq!(format!("{}", Uptime(Duration::from_secs(86_400 + 3661))), "1 day, 01:01:01");
+        assert_eq!(format!("{}", Uptime(Duration::from_secs(3 * 86_400))), "3 days, 00:00:00");
+    }
+}
@@ -0,0 +1,244 @@
+// Time spans for the kernel, kept apart from `time::Instant` so code that only needs durations
+// (the transfer protocols) doesn't drag the hardware clock along and builds on the host.
+//
+// `Duration` is kept in nanoseconds so higher resolution clock sources fit without changing the
+// API, 64 bits of them is ~584 years.
+//
+// core::time::Duration would do most of this but it has no Display, and I want the same
+// overflow behaviour as `Instant` anyway.
+
+use core::{fmt, ops::{Add, AddAssign, Sub, SubAssign}};
+
+const NANOS_PER_MICRO: u64 = 1_000;
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A span of time with nanosecond resolution
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Duration {
+    nanos: u64,
+}
+
+impl Duration {
+    pub const ZERO: Duration = Duration { nanos: 0 };
+    pub const MAX: Duration = Duration { nanos: u64::MAX };
+
+    pub const fn from_nanos(nanos: u64) -> Duration {
+        Duration { nanos }
+    }
+
+    /// The `from_*` constructors saturate at `Duration::MAX` rather than overflow
+    pub const fn from_micros(micros: u64) -> Duration {
+        Duration { nanos: micros.saturating_mul(NANOS_PER_MICRO) }
+    }
+
+    pub const fn from_millis(millis: u64) -> Duration {
+        Duration { nanos: millis.saturating_mul(NANOS_PER_MILLI) }
+    }
+
+    pub const fn from_secs(secs: u64) -> Duration {
+        Duration { nanos: secs.saturating_mul(NANOS_PER_SEC) }
+    }
+
+    pub const fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.nanos / NANOS_PER_MICRO
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.nanos / NANOS_PER_MILLI
+    }
+
+    pub const fn as_secs(&self) -> u64 {
+        self.nanos / NANOS_PER_SEC
+    }
+
+    /// Nanoseconds past the last whole second
+    pub const fn subsec_nanos(&self) -> u32 {
+        (self.nanos % NANOS_PER_SEC) as u32
+    }
+
+    pub const fn subsec_micros(&self) -> u32 {
+        self.subsec_nanos() / NANOS_PER_MICRO as u32
+    }
+
+    pub const fn subsec_millis(&self) -> u32 {
+        self.subsec_nanos() / NANOS_PER_MILLI as u32
+    }
+
+    pub const fn is_zero(&self) -> bool {
+        self.nanos == 0
+    }
+
+    pub const fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        match self.nanos.checked_add(rhs.nanos) {
+            Some(nanos) => Some(Duration { nanos }),
+            None => None,
+        }
+    }
+
+    pub const fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        match self.nanos.checked_sub(rhs.nanos) {
+            Some(nanos) => Some(Duration { nanos }),
+            None => None,
+        }
+    }
+
+    pub const fn checked_mul(self, rhs: u32) -> Option<Duration> {
+        match self.nanos.checked_mul(rhs as u64) {
+            Some(nanos) => Some(Duration { nanos }),
+            None => None,
+        }
+    }
+
+    pub const fn checked_div(self, rhs: u32) -> Option<Duration> {
+        match self.nanos.checked_div(rhs as u64) {
+            Some(nanos) => Some(Duration { nanos }),
+            None => None,
+        }
+    }
+
+    pub const fn saturating_add(self, rhs: Duration) -> Duration {
+        Duration { nanos: self.nanos.saturating_add(rhs.nanos) }
+    }
+
+    pub const fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration { nanos: self.nanos.saturating_sub(rhs.nanos) }
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        self.checked_add(rhs).expect("overflow when adding durations")
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        self.checked_sub(rhs).expect("overflow when subtracting durations")
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+/// Picks the biggest unit that keeps the number above one, e.g. `1.5s`, `250ms`, `12.345us`
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (unit, scale) = match self.nanos {
+            n if n >= NANOS_PER_SEC => ("s", NANOS_PER_SEC),
+            n if n >= NANOS_PER_MILLI => ("ms", NANOS_PER_MILLI),
+            n if n >= NANOS_PER_MICRO => ("us", NANOS_PER_MICRO),
+            _ => return write!(f, "{}ns", self.nanos),
+        };
+
+        let whole = self.nanos / scale;
+        // At most 3 decimal places, and none of the trailing zeros
+        let mut fraction = (self.nanos % scale) / (scale / 1000);
+        let mut digits = 3;
+        while digits > 0 && fraction % 10 == 0 {
+            fraction /= 10;
+            digits -= 1;
+        }
+
+        if digits == 0 {
+            write!(f, "{}{}", whole, unit)
+        } else {
+            write!(f, "{}.{:0width$}{}", whole, fraction, unit, width = digits)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let d = Duration::from_nanos(1_234_567_891);
+        assert_eq!(d.as_secs(), 1);
+        assert_eq!(d.as_millis(), 1_234);
+        assert_eq!(d.as_micros(), 1_234_567);
+        assert_eq!(d.subsec_nanos(), 234_567_891);
+        assert_eq!(d.subsec_micros(), 234_567);
+        assert_eq!(d.subsec_millis(), 234);
+
+        // The as_* conversions round down
+        assert_eq!(Duration::from_nanos(999).as_micros(), 0);
+        assert_eq!(Duration::from_micros(1_999).as_millis(), 1);
+        assert_eq!(Duration::from_millis(59_999).as_secs(), 59);
+
+        assert_eq!(Duration::from_secs(2), Duration::from_millis(2_000));
+        assert_eq!(Duration::from_millis(3), Duration::from_micros(3_000));
+        assert_eq!(Duration::from_micros(4), Duration::from_nanos(4_000));
+    }
+
+    #[test]
+    fn from_saturates() {
+        assert_eq!(Duration::from_secs(u64::MAX), Duration::MAX);
+        assert_eq!(Duration::from_millis(u64::MAX), Duration::MAX);
+        assert_eq!(Duration::from_micros(u64::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn checked_and_saturating() {
+        let one = Duration::from_secs(1);
+        let two = Duration::from_secs(2);
+
+        assert_eq!(one.checked_add(one), Some(two));
+        assert_eq!(Duration::MAX.checked_add(Duration::from_nanos(1)), None);
+        assert_eq!(two.checked_sub(one), Some(one));
+        assert_eq!(one.checked_sub(two), None);
+        assert_eq!(one.checked_mul(2), Some(two));
+        assert_eq!(Duration::MAX.checked_mul(2), None);
+        assert_eq!(two.checked_div(2), Some(one));
+        assert_eq!(one.checked_div(0), None);
+
+        assert_eq!(Duration::MAX.saturating_add(one), Duration::MAX);
+        assert_eq!(one.saturating_sub(two), Duration::ZERO);
+        assert_eq!(two.saturating_sub(one), one);
+        assert!(one.saturating_sub(one).is_zero());
+    }
+
+    #[test]
+    #[should_panic]
+    fn sub_overflow_panics() {
+        let _ = Duration::from_secs(1) - Duration::from_secs(2);
+    }
+
+    #[test]
+    fn display() {
+        let cases = [
+            (0, "0ns"),
+            (999, "999ns"),
+            (1_000, "1us"),
+            (12_345, "12.345us"),
+            (250_000_000, "250ms"),
+            (1_500_000_000, "1.5s"),
+            (1_050_000_000, "1.05s"),
+            // Only 3 places, the rest is cut off rather than rounded
+            (1_000_999_999, "1s"),
+            (86_400_000_000_000, "86400s"),
+        ];
+
+        for &(nanos, expected) in &cases {
+            assert_eq!(Duration::from_nanos(nanos).to_string(), expected);
+        }
+    }
+}
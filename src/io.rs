@@ -0,0 +1,257 @@
+// Byte stream traits the UART, memory cursors and transfer protocols all talk through. They're
+// std::io's Read and Write cut down for no_std, every read and write can fail with an `Error`
+// saying why. Printing to the console is built on them in pi::console.
+
+use core::fmt;
+use crate::duration::Duration;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorKind {
+    /// Nothing turned up in time
+    TimedOut,
+    /// Nothing wrong, try again
+    Interrupted,
+    /// The stream ended before everything wanted was read
+    UnexpectedEof,
+    /// A write went nowhere, none of it was taken
+    WriteZero,
+    /// There's no room left for what was written
+    CapacityExceeded,
+    /// An argument made no sense, e.g. seeking before the start
+    InvalidInput,
+    /// What was read made no sense
+    InvalidData,
+    /// The other end is gone, or gave up on us
+    BrokenPipe,
+    /// The other end cancelled
+    ConnectionAborted,
+    /// Anything else, e.g. a `Display` impl failing in `write_fmt`
+    Other,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.kind)
+    }
+}
+
+// Here rather than with the rest of the embedded-io impls in pi::drivers::hal, it's a foreign
+// trait on what's a foreign type over there
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        use embedded_io::ErrorKind as Kind;
+
+        match self.kind {
+            ErrorKind::TimedOut => Kind::TimedOut,
+            ErrorKind::Interrupted => Kind::Interrupted,
+            ErrorKind::WriteZero => Kind::WriteZero,
+            ErrorKind::CapacityExceeded => Kind::OutOfMemory,
+            ErrorKind::InvalidInput => Kind::InvalidInput,
+            ErrorKind::InvalidData => Kind::InvalidData,
+            ErrorKind::BrokenPipe => Kind::BrokenPipe,
+            ErrorKind::ConnectionAborted => Kind::ConnectionAborted,
+            ErrorKind::UnexpectedEof | ErrorKind::Other => Kind::Other,
+        }
+    }
+}
+
+pub trait Read {
+    /// Reads some bytes into `buf`, returning how many. Blocks until there's at least one,
+    /// or the stream's own timeout runs out. 0 means the stream has ended, or `buf` is empty.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Like `read` but gives up with `TimedOut` after `timeout`, whatever
+    /// timeout the stream has been set to. Streams that can't time out just
+    /// block.
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        self.read(buf)
+    }
+
+    /// Fills all of `buf`, or fails with `UnexpectedEof` if the stream ends first. How much of
+    /// `buf` was filled then is anyone's guess.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof)),
+                Ok(read) => buf = &mut buf[read..],
+                Err(ref e) if e.kind() == &ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+pub trait Write {
+    /// Writes some of `buf`, returning how much
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Waits for everything written to actually go out
+    fn flush(&mut self) -> Result<()>;
+
+    /// Writes all of `buf`, or fails with `WriteZero` if the stream stops taking it
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf) {
+                Ok(0) => return Err(Error::new(ErrorKind::WriteZero)),
+                Ok(written) => buf = &buf[written..],
+                Err(ref e) if e.kind() == &ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// So `write!` works. Bytes go out as formatted, nothing is done to line endings.
+    fn write_fmt(&mut self, args: fmt::Arguments) -> Result<()> {
+        // fmt::Write can't say what went wrong, so keep hold of it
+        struct Adapter<'a, W: ?Sized> {
+            inner: &'a mut W,
+            error: Result<()>,
+        }
+
+        impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.inner.write_all(s.as_bytes()).map_err(|e| {
+                    self.error = Err(e);
+                    fmt::Error
+                })
+            }
+        }
+
+        let mut adapter = Adapter { inner: self, error: Ok(()) };
+        match fmt::write(&mut adapter, args) {
+            Ok(()) => Ok(()),
+            Err(_) => adapter.error.and(Err(Error::new(ErrorKind::Other))),
+        }
+    }
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        (**self).read_timeout(buf, timeout)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        (**self).read_exact(buf)
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        (**self).write_all(buf)
+    }
+}
+
+/// Throws away everything written to it
+pub struct Sink;
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Takes at most 3 bytes a go, stopping after `limit`
+    struct Trickle {
+        data: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let count = buf.len().min(3).min(self.limit - self.data.len());
+            self.data.extend_from_slice(&buf[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let count = buf.len().min(self.len()).min(3);
+            buf[..count].copy_from_slice(&self[..count]);
+            *self = &self[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn write_all_and_fmt() {
+        let mut out = Trickle { data: Vec::new(), limit: 64 };
+        out.write_all(b"hello ").unwrap();
+        write!(out, "world and {:#x}", 255).unwrap();
+        assert_eq!(out.data, b"hello world and 0xff");
+    }
+
+    #[test]
+    fn write_all_runs_out() {
+        let mut out = Trickle { data: Vec::new(), limit: 4 };
+        assert_eq!(out.write_all(b"hello").unwrap_err().kind(), &ErrorKind::WriteZero);
+        assert_eq!(write!(out, "{}", 1).unwrap_err().kind(), &ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn read_exact() {
+        let mut input: &[u8] = b"hello world";
+        let mut buf = [0u8; 8];
+        input.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello wo");
+        assert_eq!(input.read_byte().unwrap(), b'r');
+        assert_eq!((&mut input).read_exact(&mut buf).unwrap_err().kind(), &ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,104 @@
+// Kernel symbol table, so backtraces can say `function+offset` instead of bare addresses.
+//
+// The table can't be generated until the kernel is linked, by which point every address is
+// fixed, so the makefile links twice: once to get the addresses for tools/mksyms, then again
+// with the linker script's `.ksyms` section made just big enough for the table, which is
+// copied in. See tools/mksyms for the layout.
+//
+// If the table never got filled in (e.g. built with plain cargo) the section is empty and
+// lookups just fail.
+
+use core::{fmt, str};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"KSYM");
+const HEADER_LEN: usize = 12;
+const ENTRY_LEN: usize = 12;
+
+// Nothing in rust owns the section, it's only ever found through the linker's symbols
+extern "Rust" {
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+}
+
+/// Where an address falls in the kernel
+#[derive(Clone, Copy, Debug)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub address: u64,
+    pub offset: u64,
+}
+
+/// `name+0x1c`
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// Formats an address followed by its symbol if it has one, for backtraces & register dumps
+pub struct Symbolize(pub u64);
+
+impl fmt::Display for Symbolize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)?;
+        match lookup(self.0) {
+            Some(symbol) => write!(f, " {}", symbol),
+            None => Ok(()),
+        }
+    }
+}
+
+fn table() -> &'static [u8] {
+    unsafe {
+        let start = &__ksyms_start as *const u8;
+        let end = &__ksyms_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Find the function containing `address`
+pub fn lookup(address: u64) -> Option<Symbol> {
+    let table = table();
+    if table.len() < HEADER_LEN || read_u32(table, 0) != MAGIC {
+        return None;
+    }
+
+    let count = read_u32(table, 4) as usize;
+    let names_len = read_u32(table, 8) as usize;
+    let names_start = HEADER_LEN + count * ENTRY_LEN;
+    if count == 0 || names_start + names_len > table.len() {
+        return None;
+    }
+
+    let entry = |i: usize| {
+        let at = HEADER_LEN + i * ENTRY_LEN;
+        (read_u32(table, at) as u64, read_u32(table, at + 4) as u64, read_u32(table, at + 8) as usize)
+    };
+
+    // Last symbol starting at or below the address
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if entry(mid).0 <= address {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let index = low.checked_sub(1)?;
+
+    let (start, size, name) = entry(index);
+    // Symbols from assembly have no size, give them the benefit of the doubt
+    if size != 0 && address >= start + size {
+        return None;
+    }
+
+    let name_end = if index + 1 < count { entry(index + 1).2 } else { names_len };
+    let name = str::from_utf8(table.get(names_start + name..names_start + name_end)?).ok()?;
+
+    Some(Symbol { name, address: start, offset: address - start })
+}
@@ -0,0 +1,22 @@
+// The parts of the kernel that don't touch hardware: the io stream traits, the file transfer
+// protocols built on them and the data structures that don't need an allocator. They're a
+// library so the kernel binary in main.rs can use them and `cargo test --lib` can run their
+// tests on the host, where it isn't no_std.
+//
+// Nothing in here may depend on the Pi, arch or anything else only main.rs declares.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod calendar;
+pub mod duration;
+pub mod io;
+pub mod memory;
+pub mod pull;
+pub mod stackvec;
+pub mod wheel;
+pub mod xmodem;
+pub mod ymodem;
+pub mod zmodem;
+
+#[cfg(test)]
+mod loopback;
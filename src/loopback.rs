@@ -0,0 +1,135 @@
+// A pair of connected byte streams for running the transfer protocols against each other on the
+// host, one end on each thread. Each end can be told to mess up what it writes so we can see how
+// the other end copes with a bad line.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time;
+use crate::io;
+use crate::duration::Duration;
+
+/// Something that goes wrong with a byte an end writes. Bytes are counted from 0.
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// The `n`th byte never arrives
+    Drop(usize),
+    /// The `n`th time `byte` is written it never arrives
+    DropNth { byte: u8, n: usize },
+    /// The `n`th byte arrives with its bits flipped
+    Corrupt(usize),
+    /// The `n`th byte and everything after arrives as `CAN`, like the end gave up partway
+    Cancel(usize),
+}
+
+/// One end of a loopback, made by `pair`
+pub struct End {
+    tx: Sender<u8>,
+    rx: Receiver<u8>,
+    faults: Vec<Fault>,
+    written: usize,
+    seen: [usize; 256],
+}
+
+/// Two ends connected to each other, what one writes the other reads
+pub fn pair() -> (End, End) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    (End::new(a_tx, a_rx), End::new(b_tx, b_rx))
+}
+
+impl End {
+    fn new(tx: Sender<u8>, rx: Receiver<u8>) -> End {
+        End { tx, rx, faults: Vec::new(), written: 0, seen: [0; 256] }
+    }
+
+    /// Have `fault` happen to what this end writes
+    pub fn inject(mut self, fault: Fault) -> End {
+        self.faults.push(fault);
+        self
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        let index = self.written;
+        let nth = self.seen[byte as usize];
+        self.written += 1;
+        self.seen[byte as usize] += 1;
+
+        let mut out = Some(byte);
+        for fault in &self.faults {
+            match *fault {
+                Fault::Drop(n) if n == index => out = None,
+                Fault::DropNth { byte: b, n } if b == byte && n == nth => out = None,
+                Fault::Corrupt(n) if n == index => out = out.map(|b| !b),
+                Fault::Cancel(n) if n <= index => out = Some(0x18),
+                _ => {},
+            }
+        }
+
+        // The other end hanging up is fine, it's finished with us
+        if let Some(byte) = out {
+            let _ = self.tx.send(byte);
+        }
+    }
+
+    /// Whatever else has already arrived after the first byte
+    fn read_rest(&mut self, first: u8, buf: &mut [u8]) -> usize {
+        buf[0] = first;
+        let mut read = 1;
+        while read < buf.len() {
+            match self.rx.try_recv() {
+                Ok(byte) => buf[read] = byte,
+                Err(_) => break,
+            }
+            read += 1;
+        }
+        read
+    }
+}
+
+impl io::Write for End {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.write_byte(*byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Read for End {
+    /// A hung up other end reads as a timeout, same as a silent one would
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.rx.recv() {
+            Ok(byte) => Ok(self.read_rest(byte, buf)),
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut)),
+        }
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.rx.recv_timeout(time::Duration::from_nanos(timeout.as_nanos())) {
+            Ok(byte) => Ok(self.read_rest(byte, buf)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) =>
+                Err(io::Error::new(io::ErrorKind::TimedOut)),
+        }
+    }
+}
+
+/// Somewhere for the receiving end to put things
+impl io::Write for Vec<u8> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,49 +1,67 @@
 #![no_main]
 #![no_std]
 #![feature(lang_items)]
-#![feature(const_fn_fn_ptr_basics)]
 #![feature(format_args_nl)]
-#![feature(global_asm)]
-#![feature(asm)]
 
-use crate::pi::{drivers::timer::spin_sleep_ms};
+use core::arch::asm;
 use core::fmt::Write;
 
+// Hardware independent parts, built as a library so they can be tested on the host
+use pios::{io, stackvec, xmodem};
+
+#[macro_use]
 mod pi;
 mod panic_wait;
 mod arch;
 // mod runtime_init;
-mod memory;
-// mod shell;
-mod xmodem;
+mod ksyms;
+mod shell;
 mod syncro;
-
-#[macro_use]
-mod console;
-// mod xmodem;
+mod time;
+mod timer_wheel;
+mod wallclock;
 
 
 fn kernel_init() -> !{
     
     use pi::UART_CONSOLE;
-    use console::{Read, Write};
+    use io::{Read, Write};
     use xmodem::{ModemError, ErrorKind};
     
     let dtb_pointer: u64;
     unsafe { asm!("mov {0}, x4", out(reg) dtb_pointer) }
 
+    arch::delay::init();
+
+    // Pins have to be muxed before the UART can talk to anything
+    pi::pinmux::init().unwrap();
+
     // Must initialize the UART device before we can print to the console
 
     unsafe { UART_CONSOLE.init(); }
 
+    // Exception vectors & interrupt controller need to be in place before IRQs are unmasked
+    unsafe { arch::exception::handling_init(); }
+    pi::irq::init();
+    timer_wheel::init();
+    arch::exception::local_irq_unmask();
+
+    // Sign of life for when the UART isn't plugged in
+    pi::led::init().unwrap();
+    pi::led::start_heartbeat();
+
     kernel_main(dtb_pointer);
 }
 
 fn kernel_main(dtb_pointer: u64) -> ! {
 
-    loop{
-        spin_sleep_ms(10000);
+    kprintln!("DTB Pointer is at: {:?}", dtb_pointer);
+    kprintln!("Last reset: {}", pi::drivers::watchdog::WATCHDOG.last_reset_reason());
 
-        kprintln!("DTB Pointer is at: {:?}", dtb_pointer);
+    // The loader can send the time straight after the kernel, otherwise set it with `date`
+    if wallclock::receive_from_host(time::Duration::from_millis(500)) {
+        kprintln!("Time from host: {}", wallclock::now().unwrap());
     }
+
+    shell::shell("> ");
 }
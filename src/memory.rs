@@ -1,8 +1,14 @@
 use core::ops::{RangeInclusive, Range};
-use core::fmt;
+use core::{marker::PhantomData, ptr, slice};
 
-use crate::console;
+use crate::io;
 
+/// Zero every `T` in `range` with volatile writes, so the compiler can't drop or reorder them
+///
+/// # Safety
+///
+/// Both ends of `range` must be aligned pointers into the same writable block of memory, and
+/// nothing else may be using that memory. Used on .bss before anything in it has been touched.
 pub unsafe fn zero_volatile<T>(range: RangeInclusive<*mut T>)
 where
     T: From<u8>,
@@ -16,58 +22,275 @@ where
     }
 }
 
+/// Where `MemCursor::seek` goes from
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
 
-/// This wraps a range of pointers into memory for use with our console::Write implementation
-///
-/// This is inherantly unsafe as it allows the user to write arbitrary data into memory at any location 
-/// however I need it to be able to write a new kernal binary to the Pi over UART
+/// Cursor modes
+pub enum ReadOnly {}
+pub enum WriteOnly {}
+pub enum ReadWrite {}
+
+/// Modes that can be read from
+pub trait Readable {}
+impl Readable for ReadOnly {}
+impl Readable for ReadWrite {}
+
+/// Modes that can be written to
+pub trait Writable {}
+impl Writable for WriteOnly {}
+impl Writable for ReadWrite {}
+
+/// A position in a raw region of memory that reads and/or writes move along, depending on `Mode`.
+/// This is how a new kernel gets written to memory over UART.
 ///
-/// I'm unsure if this is the best way to do this but it's the only way I could come up with to wrap memory for use with
-/// the console::Write trait
-pub struct MemCursorWriteOnly<T>
-    where 
-        T: From<u8>
-{
-    range: Range<*mut T>,
-    index: isize
+/// Nothing is ever read or written outside the region. Anything that doesn't fit is an error of
+/// kind `CapacityExceeded` instead.
+pub struct MemCursor<Mode> {
+    start: *mut u8,
+    len: usize,
+    pos: usize,
+    /// Furthest anything has been written up to, `as_slice` ends here
+    written: usize,
+    _mode: PhantomData<Mode>,
+}
+
+pub type MemReader = MemCursor<ReadOnly>;
+pub type MemWriter = MemCursor<WriteOnly>;
+
+impl MemCursor<ReadOnly> {
+    /// Reads `range`, all of which counts as written
+    ///
+    /// # Safety
+    ///
+    /// `range` must be valid to read for as long as the cursor is around
+    pub unsafe fn new(range: Range<*const u8>) -> Self {
+        let mut cursor = Self::from_range(range.start as *mut u8, range.end as *mut u8);
+        cursor.written = cursor.len;
+        cursor
+    }
+}
+
+impl MemCursor<WriteOnly> {
+    /// # Safety
+    ///
+    /// `range` must be valid to write, and not used for anything else, for as long as the
+    /// cursor is around
+    pub unsafe fn new(range: Range<*mut u8>) -> Self {
+        Self::from_range(range.start, range.end)
+    }
 }
 
-impl<T> MemCursorWriteOnly<T> where T: From<u8> {
-    pub fn new(input: Range<*mut T>) -> MemCursorWriteOnly<T>{
-        MemCursorWriteOnly {
-            range: input,
-            index: 0
+impl MemCursor<ReadWrite> {
+    /// Whatever is already in `range` can be read, but only what gets written counts for
+    /// `as_slice`
+    ///
+    /// # Safety
+    ///
+    /// `range` must be valid to read and write, and not used for anything else, for as long as
+    /// the cursor is around
+    pub unsafe fn new(range: Range<*mut u8>) -> Self {
+        Self::from_range(range.start, range.end)
+    }
+}
+
+impl<Mode> MemCursor<Mode> {
+    fn from_range(start: *mut u8, end: *mut u8) -> Self {
+        MemCursor {
+            start,
+            len: (end as usize).saturating_sub(start as usize),
+            pos: 0,
+            written: 0,
+            _mode: PhantomData,
         }
     }
 
-    fn _write_byte_inner(&mut self, byte: u8) {
-        unsafe { 
-            let mut ptr = self.range.start.offset(self.index);
-            let end = self.range.end;
+    /// Size of the region in bytes
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
 
-            
-            if ptr > end {
-                //Just don't do anything
-            }
-            else{
-                core::ptr::write_volatile(ptr, T::from(byte));
-                self.index += 1;
+    /// Offset from the start of the region the next read or write happens at
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Bytes between the position and the end of the region
+    pub fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+
+    /// Moves the position, returning the new one. The end of the region is as far as it goes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the new position would be before the start
+    /// or past the end of the region. The position doesn't move.
+    pub fn seek(&mut self, to: SeekFrom) -> io::Result<usize> {
+        let pos = match to {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => offset_by(self.pos, offset),
+            SeekFrom::End(offset) => offset_by(self.len, offset),
+        };
+
+        match pos {
+            Some(pos) if pos <= self.len => {
+                self.pos = pos;
+                Ok(pos)
             }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    /// Everything from the start of the region up to the furthest byte written, e.g. the
+    /// kernel image that was just loaded so it can be checked before jumping to it
+    pub fn as_slice(&self) -> &[u8] {
+        if self.written == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.start, self.written) }
+    }
+}
+
+fn offset_by(pos: usize, offset: isize) -> Option<usize> {
+    if offset < 0 {
+        pos.checked_sub(offset.unsigned_abs())
+    } else {
+        pos.checked_add(offset as usize)
+    }
+}
+
+impl<Mode: Readable> io::Read for MemCursor<Mode> {
+    /// Reads as much of `buf` as there is left in the region, returning how
+    /// much that was. 0 means the end has been reached.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = buf.len().min(self.remaining());
+        for (offset, byte) in buf[..count].iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile(self.start.add(self.pos + offset)) };
         }
+        self.pos += count;
+        Ok(count)
+    }
+
+    /// Fills all of `buf`, or fails with `CapacityExceeded` without reading
+    /// anything if the region ends first
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if buf.len() > self.remaining() {
+            return Err(io::Error::new(io::ErrorKind::CapacityExceeded));
+        }
+        self.read(buf)?;
+        Ok(())
     }
 }
 
+impl<Mode: Writable> io::Write for MemCursor<Mode> {
+    /// Writes all of `buf`, or fails with `CapacityExceeded` without writing
+    /// anything if it doesn't fit before the end of the region
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.remaining() {
+            return Err(io::Error::new(io::ErrorKind::CapacityExceeded));
+        }
 
-// We're reusing the console write trait as that is what I'm using on my xmodem implementation
-//
-// I should really write a separate IO::Write trait that has more useful methods and use that for my xmodem 
-// but I'm in too deep now and I'd just like to get it to work first - note for the future, write better abstracted traits
-impl<T> console::Write for MemCursorWriteOnly<T> where T:From<u8> {
-    fn write_byte(&mut self, byte: u8) {
-        self._write_byte_inner(byte);
+        for (offset, byte) in buf.iter().enumerate() {
+            unsafe { ptr::write_volatile(self.start.add(self.pos + offset), *byte) };
+        }
+        self.pos += buf.len();
+        self.written = self.written.max(self.pos);
+        Ok(buf.len())
     }
 
-    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+    /// Writes are volatile, they're already in memory
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{ErrorKind, Read, Write};
+
+    fn writer(region: &mut [u8]) -> MemWriter {
+        unsafe { MemWriter::new(region.as_mut_ptr_range()) }
+    }
+
+    #[test]
+    fn write_up_to_the_end() {
+        let mut region = [0u8; 8];
+        let mut cursor = writer(&mut region);
+        cursor.write(b"12345").unwrap();
+        assert_eq!(cursor.remaining(), 3);
+        cursor.write(b"678").unwrap();
+        assert_eq!(cursor.remaining(), 0);
+        assert_eq!(cursor.as_slice(), b"12345678");
+    }
+
+    #[test]
+    fn write_past_the_end() {
+        // The last byte is just past the end, the old cursor would write over it
+        let mut region = [0u8; 9];
+        let mut cursor = unsafe { MemWriter::new(region[..8].as_mut_ptr_range()) };
+        cursor.write(b"1234567").unwrap();
+        let error = cursor.write(b"89").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::CapacityExceeded);
+        cursor.write(b"8").unwrap();
+        assert_eq!(cursor.write(b"9").unwrap_err().kind(), &ErrorKind::CapacityExceeded);
+        assert_eq!(cursor.position(), 8);
+        assert_eq!(region, *b"12345678\0");
+    }
+
+    #[test]
+    fn write_all_past_the_end() {
+        let mut region = [0u8; 4];
+        let mut cursor = writer(&mut region);
+        assert_eq!(cursor.write_all(b"123456").unwrap_err().kind(), &ErrorKind::CapacityExceeded);
+        write!(cursor, "{}", 1234).unwrap();
+        assert_eq!(write!(cursor, "{}", 5).unwrap_err().kind(), &ErrorKind::CapacityExceeded);
+        assert_eq!(cursor.as_slice(), b"1234");
+    }
+
+    #[test]
+    fn seek() {
+        let mut region = [0u8; 8];
+        let mut cursor = writer(&mut region);
+        assert_eq!(cursor.seek(SeekFrom::End(-2)).unwrap(), 6);
+        cursor.write(b"ab").unwrap();
+        assert_eq!(cursor.seek(SeekFrom::Current(-4)).unwrap(), 4);
+        assert_eq!(cursor.seek(SeekFrom::Start(8)).unwrap(), 8);
+        assert_eq!(cursor.seek(SeekFrom::Start(9)).unwrap_err().kind(), &ErrorKind::InvalidInput);
+        assert_eq!(cursor.seek(SeekFrom::Current(-9)).unwrap_err().kind(), &ErrorKind::InvalidInput);
+        assert_eq!(cursor.position(), 8);
+        // Written up to the furthest point, gaps and all
+        assert_eq!(cursor.as_slice(), b"\0\0\0\0\0\0ab");
+    }
+
+    #[test]
+    fn read() {
+        let region = *b"hello";
+        let mut cursor = unsafe { MemReader::new(region.as_ptr_range()) };
+        let mut buf = [0u8; 3];
+        assert_eq!(cursor.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(cursor.read_exact(&mut buf).unwrap_err().kind(), &ErrorKind::CapacityExceeded);
+        assert_eq!(cursor.read(&mut buf).unwrap(), 2);
+        assert_eq!(cursor.read(&mut buf).unwrap(), 0);
+        assert_eq!(cursor.as_slice(), b"hello");
+    }
+
+    #[test]
+    fn read_back_what_was_written() {
+        let mut region = [0u8; 8];
+        let mut cursor = unsafe { MemCursor::<ReadWrite>::new(region.as_mut_ptr_range()) };
+        cursor.write(b"kernel").unwrap();
+        cursor.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = [0u8; 6];
+        cursor.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"kernel");
+        assert_eq!(cursor.read_byte().unwrap(), 0);
+        assert_eq!(cursor.as_slice(), b"kernel");
+    }
+}
@@ -1,6 +1,13 @@
-use crate::arch::cpu;
+use crate::arch::{backtrace::{self, Backtrace}, cpu, exception, smp};
 use core::{fmt, panic::PanicInfo};
-use crate::pi::console;
+use crate::pi::{console, irq};
+use crate::ksyms::Symbolize;
+use crate::syncro::{Lockable, NoLock};
+
+const CORES: usize = 4;
+
+/// Set once a core has started panicking, so a panic inside the panic handler doesn't loop
+static PANICKING: NoLock<[bool; CORES]> = NoLock::new([false; CORES]);
 
 fn _panic_print(args: fmt::Arguments) {
     use fmt::Write;
@@ -11,15 +18,48 @@ fn _panic_print(args: fmt::Arguments) {
 #[macro_export]
 macro_rules! panic_println {
     ($($arg:tt)*) => ({
-        _panic_print(format_args_nl!($($arg)*));
+        _panic_print(core::format_args_nl!($($arg)*));
     })
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Grab these before we add any frames of our own
+    let sp = backtrace::stack_pointer();
+    let fp = backtrace::frame_pointer();
+    let lr = backtrace::link_register();
+
+    exception::local_irq_mask();
+
+    let core: usize = smp::core_id();
+    if PANICKING.lock(|panicking| core::mem::replace(&mut panicking[core], true)) {
+        // Printing is what's most likely to have failed, just stop
+        cpu::wait_forever();
+    }
+
+    irq::halt_other_cores();
+
     panic_println!("\nKernel panicked: {}", info);
+    panic_println!("  core: {}  {}", core, exception::current_privilege_level());
+    panic_println!("  sp: {:#018x}  fp: {:#018x}", sp, fp);
+    panic_println!("  lr: {}", Symbolize(lr));
+    panic_println!("Backtrace:");
+    for (i, address) in Backtrace::from_frame_pointer(fp).enumerate() {
+        panic_println!("  {: >2}: {}", i, Symbolize(address));
+    }
+
+    #[cfg(feature = "panic-reset")]
+    {
+        use crate::pi::drivers::watchdog::WATCHDOG;
+
+        panic_println!("Resetting");
+        unsafe { console::panic_console().flush() };
+        WATCHDOG.reboot()
+    }
 
-    loop{}
+    // Nobody might be watching the UART, blink the line it happened on
+    #[cfg(not(feature = "panic-reset"))]
+    crate::pi::led::panic_blink(info.location().map_or(0, |location| location.line()))
 }
 
 #[lang = "eh_personality"]
@@ -1,9 +1,55 @@
-use super::drivers::uart;
-use crate::console::Write;
+use core::fmt;
+use super::{drivers::uart, pinmux, UART_CONSOLE};
+use crate::io::{self, Write};
+
+/// The UART as a terminal, `\n` goes out as `\r\n`. What `kprint!` writes to.
+pub struct Console;
+
+impl io::Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut uart = &UART_CONSOLE;
+        for line in buf.split_inclusive(|&byte| byte == b'\n' || byte == b'\r') {
+            match line.split_last() {
+                Some((b'\n', text)) | Some((b'\r', text)) => {
+                    uart.write_all(text)?;
+                    uart.write_all(b"\r\n")?;
+                }
+                _ => uart.write_all(line)?,
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        UART_CONSOLE.flush();
+        Ok(())
+    }
+}
+
+pub fn _print(args: fmt::Arguments) {
+    Console.write_fmt(args).unwrap();
+}
+
+#[macro_export]
+macro_rules! kprintln {
+    () => (kprint!("\n"));
+    ($($arg:tt)*) => ({
+        $crate::pi::console::_print(core::format_args_nl!($($arg)*));
+    })
+}
+
+#[macro_export]
+macro_rules! kprint {
+    ($($arg:tt)*) => ({
+        $crate::pi::console::_print(format_args!($($arg)*));
+    })
+}
 
 pub unsafe fn panic_console() -> uart::PanicOut {
     let mut panic_uart = uart::PanicOut::new();
     
+    pinmux::apply_stolen(pinmux::Peripheral::MiniUart);
     panic_uart.init();
     panic_uart
-}
\ No newline at end of file
+}
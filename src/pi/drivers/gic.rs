@@ -0,0 +1,191 @@
+// Driver for the GIC-400 interrupt controller on the BCM2711
+//
+// The firmware leaves the GIC enabled (enable_gic=1 is the default on the Pi 4) with
+// everything in group 1 so all we have to do is route, prioritise and enable lines.
+
+use crate::pi::memory;
+use super::common::StaticRef;
+use tock_registers::{register_bitfields, register_structs};
+use tock_registers::registers::*;
+use tock_registers::interfaces::*;
+
+/// Interrupt ID returned by the CPU interface when there is nothing pending
+pub const SPURIOUS_IRQ: usize = 1023;
+
+/// The GIC-400 supports up to 480 SPIs but the BCM2711 only wires up 256 lines
+pub const MAX_IRQ: usize = 256;
+
+/// Shared peripheral interrupts start after the 16 SGIs and 16 PPIs
+const FIRST_SPI: usize = 32;
+
+register_bitfields!{
+    u32,
+
+    /// Distributor control register
+    GICD_CTLR [
+        ENABLE OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt controller type register
+    GICD_TYPER [
+        /// Number of supported lines is 32 * (ITLINESNUMBER + 1)
+        ITLINESNUMBER OFFSET(0) NUMBITS(5) []
+    ],
+
+    /// Software generated interrupt register
+    GICD_SGIR [
+        TARGET_LIST_FILTER OFFSET(24) NUMBITS(2) [
+            TargetList = 0b00,
+            AllOthers = 0b01,
+            OnlySelf = 0b10
+        ],
+        CPU_TARGET_LIST OFFSET(16) NUMBITS(8) [],
+        SGIINTID OFFSET(0) NUMBITS(4) []
+    ],
+
+    /// CPU interface control register
+    GICC_CTLR [
+        ENABLE OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Priority mask register, only interrupts with a higher priority (lower value) are signalled
+    GICC_PMR [
+        PRIORITY OFFSET(0) NUMBITS(8) []
+    ],
+
+    /// Interrupt acknowledge register, reading it marks the interrupt active
+    GICC_IAR [
+        CPUID OFFSET(10) NUMBITS(3) [],
+        INTERRUPT_ID OFFSET(0) NUMBITS(10) []
+    ],
+
+    /// End of interrupt register, write the value read from IAR when done
+    GICC_EOIR [
+        CPUID OFFSET(10) NUMBITS(3) [],
+        EOIINTID OFFSET(0) NUMBITS(10) []
+    ]
+}
+
+register_structs!{
+    #[allow(non_snake_case)]
+    GicdRegisters {
+        (0x000 => ctlr: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x004 => typer: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x008 => _r1),
+        (0x100 => isenabler: [ReadWrite<u32>; 32]),
+        (0x180 => icenabler: [ReadWrite<u32>; 32]),
+        (0x200 => _r2),
+        (0x280 => icpendr: [ReadWrite<u32>; 32]),
+        (0x300 => _r3),
+        (0x400 => ipriorityr: [ReadWrite<u8>; 1024]),
+        (0x800 => itargetsr: [ReadWrite<u8>; 1024]),
+        (0xc00 => _r4),
+        (0xf00 => sgir: WriteOnly<u32, GICD_SGIR::Register>),
+        (0xf04 => @END),
+    }
+}
+
+register_structs!{
+    #[allow(non_snake_case)]
+    GiccRegisters {
+        (0x000 => ctlr: ReadWrite<u32, GICC_CTLR::Register>),
+        (0x004 => pmr: ReadWrite<u32, GICC_PMR::Register>),
+        (0x008 => _r1),
+        (0x00c => iar: ReadOnly<u32, GICC_IAR::Register>),
+        (0x010 => eoir: WriteOnly<u32, GICC_EOIR::Register>),
+        (0x014 => @END),
+    }
+}
+
+pub struct Gic {
+    gicd: StaticRef<GicdRegisters>,
+    gicc: StaticRef<GiccRegisters>,
+}
+
+impl Gic {
+    pub const fn new() -> Gic {
+        Gic {
+            gicd: unsafe { StaticRef::new(memory::map::GICD_START) },
+            gicc: unsafe { StaticRef::new(memory::map::GICC_START) },
+        }
+    }
+
+    /// Initialise the distributor, only needs doing once from the boot core
+    pub fn init_distributor(&self) {
+        self.gicd.ctlr.write(GICD_CTLR::ENABLE::CLEAR);
+
+        // Start from a clean slate, nothing enabled and nothing pending
+        for i in 0..self.lines() / 32 {
+            self.gicd.icenabler[i].set(u32::MAX);
+            self.gicd.icpendr[i].set(u32::MAX);
+        }
+
+        self.gicd.ctlr.write(GICD_CTLR::ENABLE::SET);
+    }
+
+    /// Initialise the banked CPU interface of the calling core
+    pub fn init_cpu_interface(&self) {
+        // Lowest possible priority mask so every interrupt gets through
+        self.gicc.pmr.write(GICC_PMR::PRIORITY.val(0xff));
+        self.gicc.ctlr.write(GICC_CTLR::ENABLE::SET);
+    }
+
+    /// Number of interrupt lines the distributor implements
+    fn lines(&self) -> usize {
+        let lines = 32 * (self.gicd.typer.read(GICD_TYPER::ITLINESNUMBER) as usize + 1);
+        lines.min(MAX_IRQ)
+    }
+
+    /// Enable an interrupt line and route it to `core`
+    ///
+    /// SGIs and PPIs are banked per core so the routing is ignored for them, they are enabled
+    /// on the calling core only.
+    pub fn enable(&self, irq: usize, core: u8) {
+        if irq >= FIRST_SPI {
+            self.gicd.itargetsr[irq].set(1 << core);
+        }
+        self.gicd.ipriorityr[irq].set(0xa0);
+        self.gicd.isenabler[irq / 32].set(1 << (irq % 32));
+    }
+
+    /// Disable an interrupt line
+    pub fn disable(&self, irq: usize) {
+        self.gicd.icenabler[irq / 32].set(1 << (irq % 32));
+    }
+
+    /// Acknowledge the highest priority pending interrupt
+    ///
+    /// Returns the raw IAR value, which must be handed back to `end_of_interrupt` untouched
+    /// as for SGIs it also holds the ID of the core that raised it. Use `interrupt_id` to get
+    /// the interrupt number, `SPURIOUS_IRQ` means nothing was pending.
+    pub fn acknowledge(&self) -> u32 {
+        self.gicc.iar.get()
+    }
+
+    /// Signal the handling of the interrupt acknowledged as `iar` is complete
+    pub fn end_of_interrupt(&self, iar: u32) {
+        self.gicc.eoir.set(iar);
+    }
+
+    /// Raise software generated interrupt `sgi` (0 - 15) on each core set in `cores`
+    pub fn send_sgi(&self, sgi: usize, cores: u8) {
+        self.gicd.sgir.write(
+            GICD_SGIR::TARGET_LIST_FILTER::TargetList
+                + GICD_SGIR::CPU_TARGET_LIST.val(cores as u32)
+                + GICD_SGIR::SGIINTID.val(sgi as u32)
+        );
+    }
+
+    /// Raise software generated interrupt `sgi` on every core except the calling one
+    pub fn send_sgi_to_others(&self, sgi: usize) {
+        self.gicd.sgir.write(GICD_SGIR::TARGET_LIST_FILTER::AllOthers + GICD_SGIR::SGIINTID.val(sgi as u32));
+    }
+}
+
+/// Extract the interrupt number from a raw IAR value
+pub fn interrupt_id(iar: u32) -> usize {
+    (iar & GICC_IAR::INTERRUPT_ID.mask) as usize
+}
+
+/// Interrupt controller shared by every core, the CPU interface registers are banked in hardware
+pub static GIC: Gic = Gic::new();
@@ -1,18 +1,57 @@
-use crate::pi::memory;
-use super::common::StaticRef;
-use tock_registers::{register_bitfields, register_structs};
+use crate::{arch::exception, pi::{irq, memory}, syncro::{Lockable, NoLock}};
+use super::{common::StaticRef, timer::SYSTEM_TIMER};
+use tock_registers::{register_bitfields, register_structs, RegisterLongName};
 use tock_registers::registers::*;
-use core::marker::PhantomData;
+use core::{fmt, marker::PhantomData};
 use tock_registers::interfaces::*;
 
+/// Number of GPIO pins on the BCM2711
+pub const PIN_COUNT: usize = 58;
+
+pub use pios::pull::Pull;
 
 pub struct GpioPin<State> {
     /// Far to lazy to create an enum to represent the pin number so it's a u32, probably too big :)
     pin: u8,
     registers: StaticRef<GpioRegisters>,
+    /// False for pins made with `GpioPin::steal`, those aren't released on drop
+    managed: bool,
     _state: PhantomData<State>,
 }
 
+pub type GpioResult<T> = core::result::Result<T, GpioError>;
+
+#[derive(Debug)]
+pub struct GpioError {
+    kind: GpioErrorKind,
+    pin: u8,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum GpioErrorKind {
+    /// Pin number is past the last pin on the BCM2711
+    InvalidPin,
+    /// Pin has already been handed out and not dropped yet
+    AlreadyClaimed,
+}
+
+impl GpioError {
+    fn new(kind: GpioErrorKind, pin: u8) -> Self {
+        Self {
+            kind,
+            pin
+        }
+    }
+
+    pub fn kind(&self) -> &GpioErrorKind {
+        &self.kind
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+}
+
 /// GPIO States available
 pub enum Uninitialized {}
 pub enum Input {}
@@ -20,6 +59,7 @@ pub enum Output {}
 pub enum Alt {}
 
 #[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Function {
     Input = 0b000,
     Output = 0b001,
@@ -31,31 +71,126 @@ pub enum Function {
     Alt5 = 0b010
 }
 
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Function::Input => "Input",
+            Function::Output => "Output",
+            Function::Alt0 => "Alt0",
+            Function::Alt1 => "Alt1",
+            Function::Alt2 => "Alt2",
+            Function::Alt3 => "Alt3",
+            Function::Alt4 => "Alt4",
+            Function::Alt5 => "Alt5",
+        })
+    }
+}
+
+/// Events an input pin can detect
+///
+/// The synchronous events are sampled with the system clock so short pulses can be
+/// missed, the async variants detect edges without sampling.
+// Only the event methods on GpioPin<Input> use it, and nothing in the kernel calls those yet
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    High,
+    Low,
+    AsyncRisingEdge,
+    AsyncFallingEdge,
+    AsyncBothEdges,
+}
+
+/// Called from the GPIO interrupt with the number of the pin whose event fired
+pub type EventHandler = fn(u8);
+
+#[derive(Clone, Copy)]
+struct EventCallback {
+    handler: EventHandler,
+    debounce_us: u64,
+    last: Option<u64>,
+}
+
+static EVENT_CALLBACKS: NoLock<[Option<EventCallback>; PIN_COUNT]> = NoLock::new([None; PIN_COUNT]);
+
 /// Complier will infer type of S via context of calling functions return type
 impl<T> GpioPin<T>{
     pub fn transition<S>(self) -> GpioPin<S> {
-        GpioPin{
+        let pin = GpioPin{
             pin: self.pin,
             registers: self.registers,
+            managed: self.managed,
             _state: PhantomData
+        };
+        // Ownership moves to the new pin, don't let the drop release it
+        core::mem::forget(self);
+        pin
+    }
+
+    pub fn number(&self) -> u8 {
+        self.pin
+    }
+
+    /// Set the pull resistor for this pin without touching the other 15 pins
+    /// sharing the same GPIO_PUP_PDN_CNTRL register
+    pub fn set_pull(&mut self, pull: Pull) {
+        let (register, _) = Pull::field(self.pin);
+        let value = self.registers.gppupdx[register].get();
+        self.registers.gppupdx[register].set(pull.encode(value, self.pin));
+    }
+
+    /// Read back the pull resistor currently set for this pin
+    ///
+    /// The reserved value 0b11 is reported as `Pull::None`
+    pub fn pull(&self) -> Pull {
+        let (register, _) = Pull::field(self.pin);
+        Pull::decode(self.registers.gppupdx[register].get(), self.pin)
+    }
+}
+
+impl<T> Drop for GpioPin<T> {
+    fn drop(&mut self) {
+        if self.managed {
+            GPIO.release(self.pin);
         }
     }
 }
 
 impl GpioPin<Uninitialized> {
-    pub fn new(pin: u8) -> GpioPin<Uninitialized> {
+    /// Pins are only handed out through `GpioManager::claim`
+    fn new(pin: u8) -> GpioPin<Uninitialized> {
         GpioPin {
             pin,
             registers: unsafe { StaticRef::new(memory::map::GPIO_START) },
+            managed: true,
+            _state: PhantomData
+        }
+    }
+
+    /// Get a pin without claiming it from the manager
+    ///
+    /// ## Safety
+    ///
+    /// The pin may be owned by someone else and gets reconfigured under their feet, only for
+    /// code like the panic console that has to get output working no matter what. `pin` must be
+    /// less than `PIN_COUNT`. Dropping the returned pin does not release it.
+    pub unsafe fn steal(pin: u8) -> GpioPin<Uninitialized> {
+        GpioPin {
+            pin,
+            registers: StaticRef::new(memory::map::GPIO_START),
+            managed: false,
             _state: PhantomData
         }
     }
 
     pub fn into_alt(self, function: Function) -> GpioPin<Alt> {
         let register = self.pin/10;
-        let pin = self.pin % 10;
-        let value = self.registers.gpfselx[register as usize].get();
-        self.registers.gpfselx[register as usize].set(value | ((function as u32) << (3 * pin)));
+        let shift = 3 * (self.pin % 10);
+        let value = self.registers.gpfselx[register as usize].get() & !(0b111 << shift);
+        self.registers.gpfselx[register as usize].set(value | ((function as u32) << shift));
         self.transition()
     }
 
@@ -80,6 +215,13 @@ impl GpioPin<Output> {
         let pin = self.pin % 32;
         self.registers.gpclrx[register as usize].set(1 << pin);
     }
+
+    /// The level the pin is currently driven to, read back from GPLEV
+    pub fn is_set(&self) -> bool {
+        let register = self.pin/32;
+        let pin = self.pin % 32;
+        (self.registers.gplevx[register as usize].get() & 1 << pin) == 1 << pin
+    }
 }
 
 impl GpioPin<Input> {
@@ -90,28 +232,201 @@ impl GpioPin<Input> {
     }
 }
 
-impl GpioPin<Alt> {
-    pub fn set_no_pud(&mut self) {
-        let register = self.pin/16;
-        let pin = self.pin % 16;
-        match pin {
-            0 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD00::NoResistor),
-            1 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD01::NoResistor),
-            2 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD02::NoResistor),
-            3 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD03::NoResistor),
-            4 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD04::NoResistor),
-            5 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD05::NoResistor),
-            6 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD06::NoResistor),
-            7 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD07::NoResistor),
-            8 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD08::NoResistor),
-            9 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD09::NoResistor),
-            10 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD10::NoResistor),
-            11 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD11::NoResistor),
-            12 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD12::NoResistor),
-            13 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD13::NoResistor),
-            14 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD14::NoResistor),
-            15 => self.registers.gppupdx[register as usize].write(GPPUPDX::GPPUPD15::NoResistor),
-            _ => panic!("No more pins")
+// Nothing in the kernel waits on pin events yet, the API is there for drivers & the shell
+#[allow(dead_code)]
+impl GpioPin<Input> {
+    /// Start detecting `event` on this pin, on top of any events already enabled
+    pub fn enable_event(&mut self, event: Event) {
+        let register = (self.pin/32) as usize;
+        let bit = 1 << (self.pin % 32);
+        match event {
+            Event::RisingEdge => set_bits(&self.registers.gprenx[register], bit),
+            Event::FallingEdge => set_bits(&self.registers.gpfenx[register], bit),
+            Event::BothEdges => {
+                set_bits(&self.registers.gprenx[register], bit);
+                set_bits(&self.registers.gpfenx[register], bit);
+            },
+            Event::High => set_bits(&self.registers.gphenx[register], bit),
+            Event::Low => set_bits(&self.registers.gplenx[register], bit),
+            Event::AsyncRisingEdge => set_bits(&self.registers.gparenx[register], bit),
+            Event::AsyncFallingEdge => set_bits(&self.registers.gpafenx[register], bit),
+            Event::AsyncBothEdges => {
+                set_bits(&self.registers.gparenx[register], bit);
+                set_bits(&self.registers.gpafenx[register], bit);
+            },
+        }
+    }
+
+    /// Stop detecting every event on this pin and clear any pending event
+    pub fn disable_events(&mut self) {
+        let register = (self.pin/32) as usize;
+        let bit = 1 << (self.pin % 32);
+        clear_bits(&self.registers.gprenx[register], bit);
+        clear_bits(&self.registers.gpfenx[register], bit);
+        clear_bits(&self.registers.gphenx[register], bit);
+        clear_bits(&self.registers.gplenx[register], bit);
+        clear_bits(&self.registers.gparenx[register], bit);
+        clear_bits(&self.registers.gpafenx[register], bit);
+        self.clear_event();
+    }
+
+    /// True if an enabled event has been detected since the last `clear_event`
+    pub fn event_detected(&self) -> bool {
+        let register = self.pin/32;
+        let pin = self.pin % 32;
+        (self.registers.gpedsx[register as usize].get() & 1 << pin) == 1 << pin
+    }
+
+    /// Acknowledge a detected event
+    ///
+    /// The status register is W1C so only this pin's bit is written, other pins are untouched.
+    /// Level events will be detected again straight away while the level is held.
+    pub fn clear_event(&mut self) {
+        let register = self.pin/32;
+        let pin = self.pin % 32;
+        self.registers.gpedsx[register as usize].set(1 << pin);
+    }
+
+    /// Call `handler` from the GPIO bank interrupt whenever an enabled event fires on this pin
+    ///
+    /// Events within `debounce_us` microseconds of the last one passed to `handler` are dropped,
+    /// use 0 to see every event. Once any pin has a handler, pending events in its bank are
+    /// acknowledged by the interrupt, so `event_detected` can't be polled for those pins.
+    /// Level events must be disabled by the handler or it will be called continuously.
+    pub fn on_event(&mut self, handler: EventHandler, debounce_us: u64) {
+        let callback = EventCallback { handler, debounce_us, last: None };
+        exception::exec_with_irq_masked(|| {
+            EVENT_CALLBACKS.lock(|callbacks| callbacks[self.pin as usize] = Some(callback))
+        });
+        // irq::init already installed handle_event_irq for the bank
+        irq::enable(bank_irq(self.pin));
+    }
+
+    /// Remove the handler registered with `on_event`, the events stay enabled
+    pub fn remove_event_handler(&mut self) {
+        exception::exec_with_irq_masked(|| EVENT_CALLBACKS.lock(|callbacks| callbacks[self.pin as usize] = None));
+    }
+}
+
+/// Hands out each GPIO pin at most once, a pin is released again when it's dropped
+pub struct GpioManager {
+    /// Bit n set if pin n is claimed
+    claimed: NoLock<u64>,
+}
+
+impl GpioManager {
+    pub const fn new() -> GpioManager {
+        GpioManager {
+            claimed: NoLock::new(0),
+        }
+    }
+
+    /// Claim `pin` for exclusive use
+    ///
+    /// # Errors
+    ///
+    /// `InvalidPin` if `pin` doesn't exist, `AlreadyClaimed` if someone else holds it.
+    pub fn claim(&self, pin: u8) -> GpioResult<GpioPin<Uninitialized>> {
+        if pin as usize >= PIN_COUNT {
+            return Err(GpioError::new(GpioErrorKind::InvalidPin, pin));
+        }
+
+        self.claimed.lock(|claimed| {
+            if *claimed & (1 << pin) != 0 {
+                return Err(GpioError::new(GpioErrorKind::AlreadyClaimed, pin));
+            }
+            *claimed |= 1 << pin;
+            Ok(GpioPin::new(pin))
+        })
+    }
+
+    /// Read the function `pin` is currently set to from GPFSEL, claimed or not
+    pub fn function(&self, pin: u8) -> Option<Function> {
+        if pin as usize >= PIN_COUNT {
+            return None;
+        }
+
+        let registers: StaticRef<GpioRegisters> = unsafe { StaticRef::new(memory::map::GPIO_START) };
+        let shift = 3 * (pin % 10);
+        match (registers.gpfselx[(pin/10) as usize].get() >> shift) & 0b111 {
+            0b000 => Some(Function::Input),
+            0b001 => Some(Function::Output),
+            0b100 => Some(Function::Alt0),
+            0b101 => Some(Function::Alt1),
+            0b110 => Some(Function::Alt2),
+            0b111 => Some(Function::Alt3),
+            0b011 => Some(Function::Alt4),
+            // Only 0b010 left
+            _ => Some(Function::Alt5),
+        }
+    }
+
+    pub fn is_claimed(&self, pin: u8) -> bool {
+        (pin as usize) < PIN_COUNT && self.claimed.lock(|claimed| *claimed & (1 << pin) != 0)
+    }
+
+    fn release(&self, pin: u8) {
+        self.claimed.lock(|claimed| *claimed &= !(1 << pin));
+    }
+}
+
+/// The single pin manager, every driver gets its pins from here
+pub static GPIO: GpioManager = GpioManager::new();
+
+// Only the event API uses these so far
+#[allow(dead_code)]
+fn set_bits<R: RegisterLongName>(register: &ReadWrite<u32, R>, bits: u32) {
+    register.set(register.get() | bits);
+}
+
+#[allow(dead_code)]
+fn clear_bits<R: RegisterLongName>(register: &ReadWrite<u32, R>, bits: u32) {
+    register.set(register.get() & !bits);
+}
+
+/// Pins are split over 3 interrupt lines: 0-27, 28-45 and 46-57
+// Only `on_event` needs it so far
+#[allow(dead_code)]
+fn bank_irq(pin: u8) -> usize {
+    match pin {
+        0..=27 => irq::GPIO_BANK_0,
+        28..=45 => irq::GPIO_BANK_1,
+        _ => irq::GPIO_BANK_2,
+    }
+}
+
+/// Shared handler for the GPIO bank interrupts
+///
+/// Every pending event is acknowledged, not just the ones with a handler, otherwise the bank
+/// interrupt would be raised again as soon as we returned.
+pub fn handle_event_irq() {
+    let registers: StaticRef<GpioRegisters> = unsafe { StaticRef::new(memory::map::GPIO_START) };
+    let now = SYSTEM_TIMER.read();
+
+    for register in 0..2 {
+        let pending = registers.gpedsx[register].get();
+        registers.gpedsx[register].set(pending);
+
+        for bit in 0..32 {
+            let pin = register * 32 + bit;
+            if pending & (1 << bit) == 0 || pin >= PIN_COUNT {
+                continue;
+            }
+
+            let handler = EVENT_CALLBACKS.lock(|callbacks| match &mut callbacks[pin] {
+                Some(callback) => match callback.last {
+                    Some(last) if now.saturating_sub(last) < callback.debounce_us => None,
+                    _ => {
+                        callback.last = Some(now);
+                        Some(callback.handler)
+                    }
+                },
+                None => None,
+            });
+
+            if let Some(handler) = handler {
+                handler(pin as u8);
+            }
         }
     }
 }
@@ -141,7 +456,7 @@ register_structs!{
         (0x088 => gpafenx:  [ReadWrite<u32, GPAFENX::Register>; 2]),
         (0x090 => _r11),
         (0x0e4 => gppupdx:  [ReadWrite<u32, GPPUPDX::Register>; 4]),
-        (0x164 => @END),
+        (0x0f4 => @END),
     }
 }
 
@@ -201,102 +516,9 @@ register_bitfields!{
         AFEN OFFSET(0) NUMBITS(32) []
     ],
     /// GPIO Pull up Pull down Control Registers
+    ///
+    /// 2 bits per pin, 16 pins per register. See `Pull` for the values
     GPPUPDX [
-        GPPUPD00 OFFSET(0) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD01 OFFSET(2) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD02 OFFSET(4) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD03 OFFSET(6) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD04 OFFSET(8) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD05 OFFSET(10) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD06 OFFSET(12) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD07 OFFSET(14) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD08 OFFSET(16) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD09 OFFSET(18) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD10 OFFSET(20) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD11 OFFSET(22) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD12 OFFSET(24) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD13 OFFSET(26) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD14 OFFSET(28) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ],
-        GPPUPD15 OFFSET(30) NUMBITS(2) [
-            NoResistor  = 0b00,
-            PullDown    = 0b01,
-            PullUp      = 0b10,
-            Reserved    = 0b11
-        ]
+        GPPUPD OFFSET(0) NUMBITS(32) []
     ]
 }
\ No newline at end of file
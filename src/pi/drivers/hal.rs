@@ -0,0 +1,131 @@
+// embedded-hal 1.0 & embedded-io implementations on top of our own drivers so community
+// sensor and display drivers can be used without rewriting them against the bespoke APIs.
+
+use crate::{io, arch::delay, time::Duration};
+use super::{gpio::{GpioPin, Input, Output}, uart::{LockedUart, MiniUart}};
+use core::convert::Infallible;
+use embedded_hal::{delay::DelayNs, digital};
+use embedded_io::{ErrorType, Read, Write};
+
+impl digital::ErrorType for GpioPin<Output> {
+    type Error = Infallible;
+}
+
+impl digital::OutputPin for GpioPin<Output> {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.clear();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.set();
+        Ok(())
+    }
+}
+
+impl digital::StatefulOutputPin for GpioPin<Output> {
+    fn is_set_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.is_set())
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.is_set())
+    }
+}
+
+impl digital::ErrorType for GpioPin<Input> {
+    type Error = Infallible;
+}
+
+impl digital::InputPin for GpioPin<Input> {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.level())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.level())
+    }
+}
+
+/// Busy wait delays from `arch::delay`, good to a counter tick (~19ns) once it's calibrated
+pub struct Delay;
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        delay::delay(Duration::from_nanos(ns as u64));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        delay::delay(Duration::from_micros(us as u64));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        delay::delay(Duration::from_millis(ms as u64));
+    }
+}
+
+// The embedded-io traits are for binary data, same as our own io traits they're built on
+
+impl ErrorType for MiniUart {
+    type Error = io::Error;
+}
+
+impl Read for MiniUart {
+    /// Blocks until at least one byte arrives, or the timeout set with `MiniUart::timeout`
+    /// expires, then returns whatever is waiting in the FIFO.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        io::Read::read(self, buf)
+    }
+}
+
+impl Write for MiniUart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        io::Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        io::Write::flush(self)
+    }
+}
+
+// Implemented for shared references too so the UART_CONSOLE static can be used directly
+
+impl ErrorType for LockedUart {
+    type Error = io::Error;
+}
+
+impl Read for LockedUart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        Read::read(&mut &*self, buf)
+    }
+}
+
+impl Write for LockedUart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        Write::write(&mut &*self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Write::flush(&mut &*self)
+    }
+}
+
+impl ErrorType for &LockedUart {
+    type Error = io::Error;
+}
+
+impl Read for &LockedUart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        io::Read::read(self, buf)
+    }
+}
+
+impl Write for &LockedUart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        io::Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        io::Write::flush(self)
+    }
+}
@@ -1,4 +1,7 @@
 pub mod common;
 pub mod timer;
 pub mod gpio;
-pub mod uart;
\ No newline at end of file
+pub mod uart;
+pub mod gic;
+pub mod hal;
+pub mod watchdog;
\ No newline at end of file
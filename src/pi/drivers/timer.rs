@@ -1,4 +1,4 @@
-use crate::pi::memory;
+use crate::{arch::exception, pi::{irq, memory}, syncro::{Lockable, NoLock}};
 use super::common::StaticRef;
 use tock_registers::{register_bitfields, register_structs};
 use tock_registers::registers::*;
@@ -54,7 +54,7 @@ register_structs! {
         (0x004 => clo: ReadOnly<u32, CLO::Register>),
         (0x008 => chi: ReadOnly<u32, CHI::Register>),
         (0x00c => cx: [ReadWrite<u32, CX::Register>; 4]),
-        (0x010 => @END),
+        (0x01c => @END),
     }
 }
 
@@ -62,6 +62,62 @@ pub struct Timer {
     registers: StaticRef<TimerBlock>
 }
 
+/// Compare channels the ARM is free to use, 0 & 2 belong to the GPU
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    One,
+    Three,
+}
+
+impl Channel {
+    /// Index into the compare registers
+    fn compare(self) -> usize {
+        match self {
+            Channel::One => 1,
+            Channel::Three => 3,
+        }
+    }
+
+    /// Index into `ALARMS`
+    fn slot(self) -> usize {
+        match self {
+            Channel::One => 0,
+            Channel::Three => 1,
+        }
+    }
+
+    fn irq(self) -> usize {
+        match self {
+            Channel::One => irq::SYSTEM_TIMER_1,
+            Channel::Three => irq::SYSTEM_TIMER_3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AlarmMode {
+    /// Fire once then forget the callback
+    OneShot,
+    /// Fire every interval until cancelled
+    Periodic,
+}
+
+/// Called from the system timer interrupt when an alarm fires
+pub type AlarmCallback = fn();
+
+#[derive(Clone, Copy)]
+struct Alarm {
+    callback: AlarmCallback,
+    mode: AlarmMode,
+    interval: u32,
+}
+
+static ALARMS: NoLock<[Option<Alarm>; 2]> = NoLock::new([None; 2]);
+
+/// Shortest alarm we'll program, any closer and the counter could pass the compare value
+/// before the write lands, leaving us waiting for it to wrap round
+pub const MIN_ALARM_US: u32 = 10;
+
 ///Wrapper for concurrent access once I implement a Mutex or lock type
 ///currently useless but it's nice to plan ahead
 pub struct TimerDevice {
@@ -81,11 +137,104 @@ impl Timer {
 
     ///Reads the system timer and returns the 64 bit counter value
     ///Number is elapsed microseconds
-
+    ///
+    ///The two halves can't be read at once, so read CHI either side of CLO and go again if
+    ///CLO rolled over in between, otherwise we'd be 71 minutes out.
     pub fn read(&self) -> u64 {
-        let low = self.registers.clo.read(CLO::CNT);
-        let high = self.registers.chi.read(CHI::CNT);
-        ((high as u64) << 32) | (low as u64)
+        loop {
+            let high = self.registers.chi.read(CHI::CNT);
+            let low = self.registers.clo.read(CLO::CNT);
+            if self.registers.chi.read(CHI::CNT) == high {
+                return ((high as u64) << 32) | (low as u64);
+            }
+        }
+    }
+
+    /// Call `callback` from the timer interrupt in `interval_us` microseconds, and again every
+    /// `interval_us` after that if `mode` is `Periodic`. Replaces any alarm already on `channel`.
+    ///
+    /// The compare registers only match against the low 32 bits of the counter so the longest
+    /// interval is about 71 minutes. Intervals shorter than `MIN_ALARM_US` are rounded up to it.
+    pub fn set_alarm(&self, channel: Channel, interval_us: u32, mode: AlarmMode, callback: AlarmCallback) {
+        let interval_us = interval_us.max(MIN_ALARM_US);
+
+        // The channel's interrupt reads the table, keep it out until the entry & compare agree
+        exception::exec_with_irq_masked(|| {
+            ALARMS.lock(|alarms| {
+                alarms[channel.slot()] = Some(Alarm { callback, mode, interval: interval_us })
+            });
+
+            self.acknowledge(channel);
+            let now = self.registers.clo.read(CLO::CNT);
+            self.registers.cx[channel.compare()].write(CX::CMP.val(now.wrapping_add(interval_us)));
+
+            irq::register_handler(channel.irq(), match channel {
+                Channel::One => handle_channel_1,
+                Channel::Three => handle_channel_3,
+            });
+        })
+    }
+
+    /// Stop the alarm on `channel`, the callback won't be called again
+    pub fn cancel_alarm(&self, channel: Channel) {
+        exception::exec_with_irq_masked(|| {
+            irq::unregister_handler(channel.irq());
+            ALARMS.lock(|alarms| alarms[channel.slot()] = None);
+            self.acknowledge(channel);
+        })
+    }
+
+    /// True if the counter has matched `channel`'s compare value since the last acknowledge
+    pub fn is_matched(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::One => self.registers.cs.is_set(CS::M1),
+            Channel::Three => self.registers.cs.is_set(CS::M3),
+        }
+    }
+
+    /// Clear the match bit of `channel`, W1C so the other channels are left alone
+    fn acknowledge(&self, channel: Channel) {
+        match channel {
+            Channel::One => self.registers.cs.write(CS::M1::Matched),
+            Channel::Three => self.registers.cs.write(CS::M3::Matched),
+        }
+    }
+}
+
+fn handle_channel_1() {
+    handle_alarm(Channel::One);
+}
+
+fn handle_channel_3() {
+    handle_alarm(Channel::Three);
+}
+
+fn handle_alarm(channel: Channel) {
+    SYSTEM_TIMER.acknowledge(channel);
+
+    let callback = ALARMS.lock(|alarms| {
+        let alarm = alarms[channel.slot()]?;
+
+        match alarm.mode {
+            AlarmMode::OneShot => alarms[channel.slot()] = None,
+            AlarmMode::Periodic => {
+                // Step from the last compare value so the period doesn't drift, unless we've
+                // fallen so far behind that the next one has already gone past
+                let registers = &SYSTEM_TIMER.registers;
+                let now = registers.clo.read(CLO::CNT);
+                let mut next = registers.cx[channel.compare()].read(CX::CMP).wrapping_add(alarm.interval);
+                if (next.wrapping_sub(now) as i32) <= 0 {
+                    next = now.wrapping_add(alarm.interval);
+                }
+                registers.cx[channel.compare()].write(CX::CMP.val(next));
+            }
+        }
+
+        Some(alarm.callback)
+    });
+
+    if let Some(callback) = callback {
+        callback();
     }
 }
 
@@ -98,17 +247,3 @@ impl TimerDevice {
 ///Initialization of system timer available outside of this crate
 
 pub const SYSTEM_TIMER: Timer = Timer::new();
-
-pub fn spin_sleep_us(delay: u64) {
-    let old = SYSTEM_TIMER.read();
-    loop{
-        let new = SYSTEM_TIMER.read();
-        if old + delay <= new {
-            break;
-        }
-    }
-}
-
-pub fn spin_sleep_ms(ms: u64){
-    spin_sleep_us(ms*1000);
-}
\ No newline at end of file
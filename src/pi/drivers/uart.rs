@@ -2,19 +2,19 @@
 // MiniUart for now so I can start chainloading my kernel
 
 use crate::{
-    console, console::{ConsoleError, ConsoleErrorKind, ConsoleResult}, pi::memory, syncro::{NoLock, Lockable},
+    io, pi::memory, syncro::{NoLock, Lockable},
+    time::{Duration, Instant},
 };
-use super::{common::StaticRef, timer::SYSTEM_TIMER};
+use super::common::StaticRef;
 use tock_registers::{register_bitfields, register_structs};
 use tock_registers::registers::*;
 use tock_registers::interfaces::*;
-use super::gpio::*;
 
 use core::fmt::{self, Write};
 
 pub struct MiniUart {
     registers: StaticRef<MiniRegisters>,
-    timeout: Option<u32>
+    timeout: Option<Duration>
 }
 
 pub use MiniUart as PanicOut;
@@ -27,18 +27,13 @@ impl MiniUart {
         }
     }
 
+    /// GPIO 14 & 15 must already be muxed to TXD1/RXD1, see `pi::pinmux`
     pub fn init(&mut self) {
         // we might be in a panic so flush the buffer
         self.flush();
         
         self.registers.auxenable.write(AUXENABLE::UART::SET);
 
-        let mut pin14 = GpioPin::new(14).into_alt(Function::Alt5);
-        pin14.set_no_pud();
-
-        let mut pin15 = GpioPin::new(15).into_alt(Function::Alt5);
-        pin15.set_no_pud();
-
         self.registers.lcr.set(3);
 
         let divisor: u32 = (500000000/(115200*8)) - 1;
@@ -47,8 +42,14 @@ impl MiniUart {
         self.registers.cntl.modify(CNTL::RXENABLE::SET + CNTL::TXENABLE::SET);
     }
 
-    pub fn timeout(&mut self, ms: u32) {
-        self.timeout = Some(ms);
+    /// Give up on `read` after `timeout`
+    pub fn timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Make `read` wait forever again
+    pub fn clear_timeout(&mut self) {
+        self.timeout = None;
     }
 
     pub fn write_byte(&mut self, byte: u8) {
@@ -56,8 +57,9 @@ impl MiniUart {
         self.registers.io.set(byte);
     }
 
-    pub fn wait_for_byte(&self) -> Result<(), ()> {
-        match self.timeout {
+    /// Wait up to `timeout` for a byte to arrive, `None` waits forever
+    pub fn wait_for_byte_timeout(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        match timeout {
             None => {
                 loop{
                     if self.has_byte(){
@@ -66,14 +68,16 @@ impl MiniUart {
                 }
             },
             Some(timeout) => {
-                let time = SYSTEM_TIMER.read();
-                let deadline = time + (timeout as u64) * 1000;
-                while deadline >= SYSTEM_TIMER.read() {
+                // Always look at least once, so a zero timeout polls
+                let start = Instant::now();
+                loop {
                     if self.has_byte(){
                         return Ok(())
                     }
+                    if start.elapsed() > timeout {
+                        return Err(())
+                    }
                 }
-                Err(())
             }
         }
     }
@@ -100,7 +104,7 @@ impl MiniUart {
         }
     }
 
-    fn flush(&self) {
+    pub fn flush(&self) {
         while !self.registers.lsr.matches_any(LSR::TXEMPTY::SET) {};
     }
 }
@@ -146,8 +150,12 @@ impl LockedUart {
         Ok(())
     }
 
-    pub fn timeout(&self, ms: u32) {
-        self.inner.lock(|inner| inner.timeout(ms));
+    pub fn timeout(&self, timeout: Duration) {
+        self.inner.lock(|inner| inner.timeout(timeout));
+    }
+
+    pub fn clear_timeout(&self) {
+        self.inner.lock(|inner| inner.clear_timeout());
     }
 
     pub fn flush(&self) {
@@ -155,22 +163,83 @@ impl LockedUart {
     }
 }
 
-impl console::Write for LockedUart {
-    fn write_byte(&mut self, byte: u8) {
-        self.inner.lock(|inner| inner.write_byte(byte));
+impl Lockable for LockedUart {
+    type Data = MiniUart;
+
+    fn lock<R>(&self, f: impl FnOnce(&mut MiniUart) -> R) -> R {
+        self.inner.lock(f)
     }
-    fn write_fmt(&self, args: core::fmt::Arguments) -> fmt::Result {
-        self.inner.lock(|inner| fmt::Write::write_fmt(inner, args))
+}
+
+// The io traits are for binary data, unlike fmt::Write nothing is done to line endings
+
+impl MiniUart {
+    /// Waits up to `timeout` for a byte, `None` waits forever, then takes whatever is waiting in
+    /// the FIFO. An empty `buf` returns straight away.
+    fn read_within(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.wait_for_byte_timeout(timeout)
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut))?;
+
+        let mut read = 0;
+        while read < buf.len() && self.has_byte() {
+            buf[read] = self.read_byte();
+            read += 1;
+        }
+
+        Ok(read)
     }
 }
 
-impl console::Read for LockedUart {
-    fn read_byte(&self) -> ConsoleResult<u8> {
-        self.inner.lock(
-            |inner| match inner.wait_for_byte() {
-                Ok(()) => Ok(inner.read_byte()),
-                Err(()) => Err(ConsoleError::new(ConsoleErrorKind::TimedOut))
-        })
+impl io::Read for MiniUart {
+    /// Blocks until at least one byte arrives, or the timeout set with `MiniUart::timeout`
+    /// expires, then returns whatever is waiting in the FIFO.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_within(buf, self.timeout)
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.read_within(buf, Some(timeout))
+    }
+}
+
+impl io::Write for MiniUart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.write_byte(*byte);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        MiniUart::flush(self);
+        Ok(())
+    }
+}
+
+// Implemented for shared references so the UART_CONSOLE static can be used directly
+
+impl io::Read for &LockedUart {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.lock(|inner| io::Read::read(inner, buf))
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.inner.lock(|inner| io::Read::read_timeout(inner, buf, timeout))
+    }
+}
+
+impl io::Write for &LockedUart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock(|inner| io::Write::write(inner, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock(|inner| io::Write::flush(inner))
     }
 }
 
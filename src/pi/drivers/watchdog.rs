@@ -0,0 +1,205 @@
+// BCM2711 power management watchdog, the only way to reset the board from software.
+//
+// The PM block isn't in the BCM2711 datasheet, register layout & magic numbers are from the
+// BCM2835 one and Linux's bcm2835_wdt.c. Every write has to carry the password in the top
+// byte or it's ignored.
+//
+// The watchdog counts down in 1/65536s ticks, about 16 seconds at most, and resets the board
+// when it hits zero unless it's fed first.
+
+use crate::{arch::cpu, pi::memory, syncro::{Lockable, NoLock}, time::Duration};
+use super::common::StaticRef;
+use tock_registers::{register_bitfields, register_structs};
+use tock_registers::registers::*;
+use tock_registers::interfaces::*;
+use core::fmt;
+
+const PASSWORD: u32 = 0x5a00_0000;
+const PASSWORD_MASK: u32 = 0xff00_0000;
+
+/// Watchdog ticks per second
+const TICKS_PER_SEC: u64 = 1 << 16;
+const MAX_TICKS: u32 = 0xf_ffff;
+
+/// Short enough to be instant, long enough for the write to RSTC to land first
+const REBOOT_TICKS: u32 = 10;
+
+/// Partition number the firmware treats as "stay halted" rather than booting, bits 0, 2, 4,
+/// 6, 8 & 10 of RSTS
+const HALT_PARTITION: u32 = 0x555;
+
+register_bitfields! {
+    u32,
+
+    RSTC [
+        /// What happens when the watchdog expires
+        WRCFG OFFSET(4) NUMBITS(2) [
+            Clear = 0b00,
+            FullReset = 0b10
+        ],
+
+        /// Written with WRCFG cleared to stop the watchdog
+        RESET OFFSET(0) NUMBITS(12) [
+            Stop = 0x102
+        ]
+    ],
+
+    RSTS [
+        /// Power on reset
+        HADPOR OFFSET(12) NUMBITS(1) [],
+        /// Software reset, hard/full/quick
+        HADSRH OFFSET(10) NUMBITS(1) [],
+        HADSRF OFFSET(9) NUMBITS(1) [],
+        HADSRQ OFFSET(8) NUMBITS(1) [],
+        /// Watchdog reset, hard/full/quick
+        HADWRH OFFSET(6) NUMBITS(1) [],
+        HADWRF OFFSET(5) NUMBITS(1) [],
+        HADWRQ OFFSET(4) NUMBITS(1) [],
+        /// Debugger reset, hard/full/quick
+        HADDRH OFFSET(2) NUMBITS(1) [],
+        HADDRF OFFSET(1) NUMBITS(1) [],
+        HADDRQ OFFSET(0) NUMBITS(1) []
+    ],
+
+    WDOG [
+        /// Ticks left until reset
+        TIME OFFSET(0) NUMBITS(20) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    PmRegisters {
+        (0x000 => _reserved),
+        (0x01c => rstc: ReadWrite<u32, RSTC::Register>),
+        (0x020 => rsts: ReadWrite<u32, RSTS::Register>),
+        (0x024 => wdog: ReadWrite<u32, WDOG::Register>),
+        (0x028 => @END),
+    }
+}
+
+/// Why the board last came out of reset, read from PM_RSTS
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResetReason {
+    PowerOn,
+    /// Woken from `poweroff`
+    Halt,
+    /// The watchdog expired, including `reboot` which uses it
+    Watchdog,
+    Software,
+    Debugger,
+    /// None of the flags we know about, holds the raw RSTS value
+    Unknown(u32),
+}
+
+impl fmt::Display for ResetReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResetReason::PowerOn => write!(f, "power on"),
+            ResetReason::Halt => write!(f, "woken from halt"),
+            ResetReason::Watchdog => write!(f, "watchdog"),
+            ResetReason::Software => write!(f, "software reset"),
+            ResetReason::Debugger => write!(f, "debugger"),
+            ResetReason::Unknown(rsts) => write!(f, "unknown (RSTS {:#x})", rsts),
+        }
+    }
+}
+
+/// Watchdog timeout in ticks, `None` while stopped
+static TIMEOUT: NoLock<Option<u32>> = NoLock::new(None);
+
+pub struct Watchdog {
+    registers: StaticRef<PmRegisters>,
+}
+
+impl Watchdog {
+    pub const fn new() -> Watchdog {
+        Watchdog {
+            registers: unsafe { StaticRef::new(memory::map::PM_START) },
+        }
+    }
+
+    /// Reset the board unless `feed` is called at least every `timeout`
+    ///
+    /// The timeout is clamped to the ~16 seconds the hardware can count.
+    pub fn start(&self, timeout: Duration) {
+        let ticks = (timeout.as_micros().saturating_mul(TICKS_PER_SEC) / 1_000_000).clamp(1, MAX_TICKS as u64) as u32;
+
+        TIMEOUT.lock(|timeout| *timeout = Some(ticks));
+        self.arm(ticks);
+    }
+
+    /// Put the watchdog back to its full timeout, does nothing if it isn't running
+    pub fn feed(&self) {
+        if let Some(ticks) = TIMEOUT.lock(|timeout| *timeout) {
+            self.arm(ticks);
+        }
+    }
+
+    pub fn stop(&self) {
+        TIMEOUT.lock(|timeout| *timeout = None);
+        self.registers.rstc.set(PASSWORD | RSTC::RESET::Stop.value);
+    }
+
+    pub fn is_running(&self) -> bool {
+        TIMEOUT.lock(|timeout| timeout.is_some())
+    }
+
+    /// Time until the board resets if nobody feeds the watchdog
+    pub fn time_left(&self) -> Option<Duration> {
+        self.is_running().then(|| {
+            let ticks = self.registers.wdog.read(WDOG::TIME) as u64;
+            Duration::from_micros(ticks * 1_000_000 / TICKS_PER_SEC)
+        })
+    }
+
+    /// Decode why the board last reset
+    pub fn last_reset_reason(&self) -> ResetReason {
+        let rsts = self.registers.rsts.get();
+        let flags = self.registers.rsts.extract();
+
+        // The halt partition overlaps the reset flags so it has to be checked first
+        if rsts & HALT_PARTITION == HALT_PARTITION {
+            ResetReason::Halt
+        } else if flags.is_set(RSTS::HADPOR) {
+            ResetReason::PowerOn
+        } else if flags.matches_any(RSTS::HADWRH::SET + RSTS::HADWRF::SET + RSTS::HADWRQ::SET) {
+            ResetReason::Watchdog
+        } else if flags.matches_any(RSTS::HADSRH::SET + RSTS::HADSRF::SET + RSTS::HADSRQ::SET) {
+            ResetReason::Software
+        } else if flags.matches_any(RSTS::HADDRH::SET + RSTS::HADDRF::SET + RSTS::HADDRQ::SET) {
+            ResetReason::Debugger
+        } else {
+            ResetReason::Unknown(rsts)
+        }
+    }
+
+    /// Reset the board straight away
+    pub fn reboot(&self) -> ! {
+        self.set_partition(0);
+        self.arm(REBOOT_TICKS);
+        cpu::wait_forever()
+    }
+
+    /// Reset into the firmware's halt state, the board stays off until it's power cycled
+    pub fn poweroff(&self) -> ! {
+        self.set_partition(HALT_PARTITION);
+        self.arm(REBOOT_TICKS);
+        cpu::wait_forever()
+    }
+
+    fn arm(&self, ticks: u32) {
+        self.registers.wdog.set(PASSWORD | (ticks & MAX_TICKS));
+
+        let rstc = self.registers.rstc.get() & !(PASSWORD_MASK | RSTC::WRCFG.mask << RSTC::WRCFG.shift);
+        self.registers.rstc.set(PASSWORD | rstc | RSTC::WRCFG::FullReset.value);
+    }
+
+    /// The firmware reads the partition to boot from back out of RSTS after the reset
+    fn set_partition(&self, partition: u32) {
+        let rsts = self.registers.rsts.get() & !(PASSWORD_MASK | HALT_PARTITION);
+        self.registers.rsts.set(PASSWORD | rsts | partition);
+    }
+}
+
+pub const WATCHDOG: Watchdog = Watchdog::new();
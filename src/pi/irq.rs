@@ -0,0 +1,91 @@
+// Board level interrupt numbers and dispatch of IRQs to registered handlers
+//
+// Handlers are plain function pointers as there's no allocator to box closures up with,
+// anything a handler needs has to live in a static.
+
+use super::drivers::{gic::{self, GIC, MAX_IRQ, SPURIOUS_IRQ}, gpio};
+use crate::{arch::{cpu, exception, smp}, syncro::{Lockable, NoLock}};
+
+/// SGI sent to stop the other cores when one panics
+pub const IPI_HALT: usize = 0;
+
+/// Non-secure physical generic timer, a PPI so every core has its own
+pub const GENERIC_TIMER: usize = 30;
+
+/// System timer compare channels 1 & 3, VideoCore IRQs 1 & 3
+pub const SYSTEM_TIMER_1: usize = 97;
+pub const SYSTEM_TIMER_3: usize = 99;
+
+/// GPIO bank interrupts, VideoCore IRQs 49 - 52 which the GIC sees as SPIs from ID 96
+pub const GPIO_BANK_0: usize = 145;
+pub const GPIO_BANK_1: usize = 146;
+pub const GPIO_BANK_2: usize = 147;
+
+pub type IrqHandler = fn();
+
+static HANDLERS: NoLock<[Option<IrqHandler>; MAX_IRQ]> = NoLock::new([None; MAX_IRQ]);
+
+/// Bring up the interrupt controller, must run on the boot core before IRQs are unmasked
+pub fn init() {
+    GIC.init_distributor();
+    GIC.init_cpu_interface();
+    register_handler(IPI_HALT, halt_core);
+
+    // Left disabled until a pin asks for its events, see `GpioPin::on_event`
+    for &bank in &[GPIO_BANK_0, GPIO_BANK_1, GPIO_BANK_2] {
+        set_handler(bank, gpio::handle_event_irq);
+    }
+}
+
+/// Stop every other core where it is, they never come back
+pub fn halt_other_cores() {
+    GIC.send_sgi_to_others(IPI_HALT);
+}
+
+fn halt_core() {
+    exception::local_irq_mask();
+    cpu::wait_forever()
+}
+
+/// Register `handler` for `irq` and enable the line, routed to the calling core
+///
+/// Any previous handler for the line is replaced.
+pub fn register_handler(irq: usize, handler: IrqHandler) {
+    set_handler(irq, handler);
+    enable(irq);
+}
+
+/// Set the handler for `irq` without enabling the line
+pub fn set_handler(irq: usize, handler: IrqHandler) {
+    // The handlers are read from the IRQ vector, keep it out while we write
+    exception::exec_with_irq_masked(|| HANDLERS.lock(|handlers| handlers[irq] = Some(handler)));
+}
+
+/// Enable `irq`, routed to the calling core
+pub fn enable(irq: usize) {
+    GIC.enable(irq, smp::core_id());
+}
+
+/// Disable `irq` and forget its handler
+pub fn unregister_handler(irq: usize) {
+    GIC.disable(irq);
+    exception::exec_with_irq_masked(|| HANDLERS.lock(|handlers| handlers[irq] = None));
+}
+
+/// Called from the IRQ exception vector
+pub fn handle_irq() {
+    let iar = GIC.acknowledge();
+    let irq = gic::interrupt_id(iar);
+
+    if irq == SPURIOUS_IRQ {
+        return;
+    }
+
+    match HANDLERS.lock(|handlers| handlers.get(irq).copied().flatten()) {
+        Some(handler) => handler(),
+        // Nobody wants it, turn it off rather than take it again forever
+        None => GIC.disable(irq),
+    }
+
+    GIC.end_of_interrupt(iar);
+}
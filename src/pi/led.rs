@@ -0,0 +1,117 @@
+// The Pi 4's green ACT LED, on GPIO 42 and lit when the pin is high.
+//
+// Normally it does a double-blink heartbeat off the timer wheel so there's a sign of life
+// without the UART plugged in. After a panic it blinks out where the panic happened instead.
+
+use super::drivers::gpio::{GpioPin, GpioResult, Output, GPIO};
+use crate::{arch::delay, syncro::{Lockable, NoLock}, time::Duration, timer_wheel::{self, TimeoutHandle}};
+
+pub const ACT_LED_PIN: u8 = 42;
+
+/// On/off times of one heartbeat, the same lub-dub Linux uses
+const HEARTBEAT: [Duration; 4] = [
+    Duration::from_millis(70),
+    Duration::from_millis(180),
+    Duration::from_millis(70),
+    Duration::from_millis(680),
+];
+
+const SHORT_BLINK: Duration = Duration::from_millis(200);
+const LONG_BLINK: Duration = Duration::from_millis(800);
+const BLINK_GAP: Duration = Duration::from_millis(300);
+const DIGIT_GAP: Duration = Duration::from_millis(1000);
+const CODE_GAP: Duration = Duration::from_millis(3000);
+
+static LED: NoLock<Option<GpioPin<Output>>> = NoLock::new(None);
+
+/// Pending heartbeat step, so it can be stopped
+static HEARTBEAT_TIMEOUT: NoLock<Option<TimeoutHandle>> = NoLock::new(None);
+
+/// Claim the LED pin, it starts off
+pub fn init() -> GpioResult<()> {
+    let mut pin = GPIO.claim(ACT_LED_PIN)?.into_output();
+    pin.clear();
+    LED.lock(|led| *led = Some(pin));
+    Ok(())
+}
+
+pub fn set(on: bool) {
+    LED.lock(|led| match led {
+        Some(pin) if on => pin.set(),
+        Some(pin) => pin.clear(),
+        None => {},
+    });
+}
+
+pub fn toggle() {
+    LED.lock(|led| {
+        if let Some(pin) = led {
+            if pin.is_set() { pin.clear() } else { pin.set() }
+        }
+    });
+}
+
+/// Blink the heartbeat pattern until `stop_heartbeat`, needs `init` & the timer wheel
+pub fn start_heartbeat() {
+    stop_heartbeat();
+    heartbeat_step(0);
+}
+
+pub fn stop_heartbeat() {
+    if let Some(handle) = HEARTBEAT_TIMEOUT.lock(|timeout| timeout.take()) {
+        timer_wheel::cancel(handle);
+    }
+    set(false);
+}
+
+/// Even steps turn the LED on, odd ones off
+fn heartbeat_step(step: usize) {
+    set(step % 2 == 0);
+
+    let next = timer_wheel::schedule(HEARTBEAT[step].as_micros(), heartbeat_step, (step + 1) % HEARTBEAT.len());
+    HEARTBEAT_TIMEOUT.lock(|timeout| *timeout = next);
+}
+
+/// Blink `code` forever, for the panic handler
+///
+/// One long blink, then each decimal digit of `code` as that many short blinks (ten for a
+/// zero), then a long pause before it starts again. Only uses the LED pin & delays so it works
+/// with interrupts off and whatever state the rest of the kernel is in.
+pub fn panic_blink(code: u32) -> ! {
+    // Whoever had the pin isn't going to be using it again
+    let mut pin = unsafe { GpioPin::steal(ACT_LED_PIN) }.into_output();
+
+    let mut digits = [0u8; 10];
+    let mut count = 0;
+    let mut rest = code;
+    loop {
+        digits[count] = (rest % 10) as u8;
+        count += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+
+    loop {
+        blink(&mut pin, LONG_BLINK);
+        delay::delay(DIGIT_GAP);
+
+        for &digit in digits[..count].iter().rev() {
+            let blinks = if digit == 0 { 10 } else { digit };
+            for _ in 0..blinks {
+                blink(&mut pin, SHORT_BLINK);
+            }
+            delay::delay(DIGIT_GAP);
+        }
+
+        delay::delay(CODE_GAP);
+    }
+}
+
+fn blink(pin: &mut GpioPin<Output>, on: Duration) {
+    pin.set();
+    delay::delay(on);
+    pin.clear();
+    delay::delay(BLINK_GAP);
+}
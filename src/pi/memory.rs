@@ -19,12 +19,22 @@ pub mod map {
     pub const GPIO_OFFSET: usize            = 0x0020_0000;
     pub const TIMER_OFFSET: usize           = 0x0000_3000;
 
+    /// Power management, home of the watchdog
+    pub const PM_OFFSET: usize              = 0x0010_0000;
+
     /// Auxiliary peripherals: Mini UART, SPI1 & SPI2
     pub const AUX_OFFSET: usize             = 0x0021_5000;
 
+    /// GIC-400 distributor & CPU interface, part of the ARM local peripherals
+    pub const GICD_OFFSET: usize            = 0x0184_1000;
+    pub const GICC_OFFSET: usize            = 0x0184_2000;
+
     pub const GPIO_START: usize             = IO_BASE + GPIO_OFFSET;
     pub const TIMER_START: usize            = IO_BASE + TIMER_OFFSET;
+    pub const PM_START: usize               = IO_BASE + PM_OFFSET;
     pub const AUX_START: usize              = IO_BASE + AUX_OFFSET;
+    pub const GICD_START: usize             = IO_BASE + GICD_OFFSET;
+    pub const GICC_START: usize             = IO_BASE + GICC_OFFSET;
 }
 
 #[inline(always)]
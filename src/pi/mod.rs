@@ -1,7 +1,12 @@
+// First so kprint!/kprintln! are in scope for everything after it
+#[macro_use]
+pub mod console;
 pub mod memory;
 pub mod cpu;
 pub mod drivers;
-pub mod console;
+pub mod irq;
+pub mod pinmux;
+pub mod led;
 
 // Turns out the RPI4 doesn't like ATAGs & uses a device tree.
 // I'll come back to memory allocators later - first I'll initialize the MMU/interrupts
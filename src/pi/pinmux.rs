@@ -0,0 +1,90 @@
+// Board pin-mux table: which peripheral uses which GPIO pins and in which alternate function.
+//
+// Drivers don't set GPFSEL themselves any more, everything the board uses is listed in
+// `BOARD_PINMUX`, checked for conflicts at compile time and applied once during boot. The
+// pins are claimed from the GPIO manager and held here so nobody else can grab them.
+
+use super::drivers::gpio::{Alt, Function, GpioPin, GpioResult, Pull, Uninitialized, GPIO, PIN_COUNT};
+use crate::syncro::{Lockable, NoLock};
+use core::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Peripheral {
+    MiniUart,
+}
+
+impl fmt::Display for Peripheral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Peripheral::MiniUart => "MiniUart",
+        })
+    }
+}
+
+pub struct PinMux {
+    pub peripheral: Peripheral,
+    /// Name of the signal from the BCM2711 alternate function table
+    pub signal: &'static str,
+    pub pin: u8,
+    pub function: Function,
+    pub pull: Pull,
+}
+
+pub const BOARD_PINMUX: &[PinMux] = &[
+    PinMux { peripheral: Peripheral::MiniUart, signal: "TXD1", pin: 14, function: Function::Alt5, pull: Pull::None },
+    PinMux { peripheral: Peripheral::MiniUart, signal: "RXD1", pin: 15, function: Function::Alt5, pull: Pull::None },
+];
+
+// Fails the build if the table uses a pin twice or a pin that doesn't exist
+const _: () = check_conflicts(BOARD_PINMUX);
+
+const fn check_conflicts(table: &[PinMux]) {
+    let mut i = 0;
+    while i < table.len() {
+        assert!((table[i].pin as usize) < PIN_COUNT, "Board pin-mux uses a pin that doesn't exist");
+
+        let mut j = i + 1;
+        while j < table.len() {
+            assert!(table[i].pin != table[j].pin, "Board pin-mux uses the same pin twice");
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+const UNCLAIMED: Option<GpioPin<Alt>> = None;
+
+/// The pins claimed by `init`, in the same order as `BOARD_PINMUX`
+static PINS: NoLock<[Option<GpioPin<Alt>>; BOARD_PINMUX.len()]> = NoLock::new([UNCLAIMED; BOARD_PINMUX.len()]);
+
+fn configure(pin: GpioPin<Uninitialized>, entry: &PinMux) -> GpioPin<Alt> {
+    let mut pin = pin.into_alt(entry.function);
+    pin.set_pull(entry.pull);
+    pin
+}
+
+/// Claim and configure every pin in the board table, must run before any driver is initialised
+pub fn init() -> GpioResult<()> {
+    for (i, entry) in BOARD_PINMUX.iter().enumerate() {
+        let pin = configure(GPIO.claim(entry.pin)?, entry);
+        PINS.lock(|pins| pins[i] = Some(pin));
+    }
+
+    Ok(())
+}
+
+/// Configure the pins of `peripheral` without going through the GPIO manager
+///
+/// ## Safety
+///
+/// Only for the panic console, which has to work whether or not `init` has run
+pub unsafe fn apply_stolen(peripheral: Peripheral) {
+    for entry in BOARD_PINMUX.iter().filter(|entry| entry.peripheral == peripheral) {
+        configure(GpioPin::steal(entry.pin), entry);
+    }
+}
+
+/// True if the board table has been applied and `entry` is held by it
+pub fn is_applied(entry: &PinMux) -> bool {
+    PINS.lock(|pins| pins.iter().flatten().any(|pin| pin.number() == entry.pin))
+}
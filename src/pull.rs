@@ -0,0 +1,87 @@
+// GPIO pull resistor encoding for the BCM2711's GPIO_PUP_PDN_CNTRL registers: 2 bits a pin,
+// 16 pins to a register. Only the bit twiddling lives here so it can be tested on the host,
+// the GPIO driver does the register access.
+
+use core::fmt;
+
+/// Pull up/down resistor setting, values match the GPIO_PUP_PDN_CNTRL fields
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pull {
+    None = 0b00,
+    Up = 0b01,
+    Down = 0b10,
+}
+
+impl fmt::Display for Pull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Pull::None => "None",
+            Pull::Up => "Up",
+            Pull::Down => "Down",
+        })
+    }
+}
+
+impl Pull {
+    /// Index of the register holding `pin`'s field and the field's shift within it
+    pub fn field(pin: u8) -> (usize, u32) {
+        ((pin / 16) as usize, 2 * (pin % 16) as u32)
+    }
+
+    /// `value` with `pin`'s field set to this pull, the other 15 pins left alone
+    pub fn encode(self, value: u32, pin: u8) -> u32 {
+        let (_, shift) = Pull::field(pin);
+        (value & !(0b11 << shift)) | ((self as u32) << shift)
+    }
+
+    /// The pull set for `pin` in `value`
+    ///
+    /// The reserved value 0b11 is reported as `Pull::None`
+    pub fn decode(value: u32, pin: u8) -> Pull {
+        let (_, shift) = Pull::field(pin);
+        match (value >> shift) & 0b11 {
+            0b01 => Pull::Up,
+            0b10 => Pull::Down,
+            _ => Pull::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_values() {
+        // BCM2711 peripherals datasheet: 00 none, 01 pull up, 10 pull down
+        assert_eq!(Pull::None as u32, 0b00);
+        assert_eq!(Pull::Up as u32, 0b01);
+        assert_eq!(Pull::Down as u32, 0b10);
+
+        assert_eq!(Pull::field(0), (0, 0));
+        assert_eq!(Pull::field(15), (0, 30));
+        assert_eq!(Pull::field(16), (1, 0));
+        assert_eq!(Pull::field(57), (3, 18));
+    }
+
+    #[test]
+    fn encode_leaves_other_pins() {
+        let value = Pull::Up.encode(0, 1);
+        assert_eq!(value, 0b01 << 2);
+        assert_eq!(Pull::Down.encode(u32::MAX, 1), !(0b01 << 2));
+        assert_eq!(Pull::None.encode(u32::MAX, 31), !(0b11 << 30));
+    }
+
+    #[test]
+    fn roundtrip() {
+        for pin in 0..58 {
+            for &pull in &[Pull::None, Pull::Up, Pull::Down] {
+                let value = pull.encode(0x5555_5555, pin);
+                assert_eq!(Pull::decode(value, pin), pull, "pin {}", pin);
+            }
+        }
+
+        assert_eq!(Pull::decode(0b11 << 4, 2), Pull::None);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::pi;
-use crate::memory;
+use pios::memory;
 
 #[inline(always)]
 unsafe fn zero_bss(){
@@ -1,24 +1,45 @@
-use crate::pi::drivers::{timer::spin_sleep_ms};
+use crate::pi::drivers::{gpio::GPIO, watchdog::WATCHDOG};
+use crate::pi::{pinmux, UART_CONSOLE};
+use crate::io::Read;
+use crate::stackvec::StackVec;
+use crate::arch::backtrace::Backtrace;
+use crate::ksyms::{self, Symbolize};
+use crate::time::{self, Duration};
+use crate::wallclock::{self, DateTime};
 use core::str;
 
-use crate::kprintln;
+/// Longest line the shell will accept
+const MAX_LINE: usize = 512;
+/// Most arguments a line is split into, including the command name
+const MAX_ARGS: usize = 64;
 
-use staticvec::{staticvec, StaticVec};
+/// How often the shell feeds the watchdog while waiting for input, keep it well inside the
+/// shortest timeout anyone would start it with
+const WATCHDOG_FEED_INTERVAL: Duration = Duration::from_millis(500);
+
+const BELL: u8 = 0x07;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+enum Error {
+    Empty,
+    TooManyArgs,
+}
 
 struct Command<'a> {
-    args: StaticVec<&'a str, 65>,
+    args: StackVec<'a, &'a str>,
 }
 
 impl<'a> Command<'a> {
-    fn parse(s: &str) -> Result<Command, &'static str> {
-        let mut args = StaticVec::<&str, 65>::new();
+    fn parse(s: &'a str, buf: &'a mut [&'a str]) -> Result<Command<'a>, Error> {
+        let mut args = StackVec::new(buf);
 
         for arg in s.split(' ').filter(|a| !a.is_empty()) {
-            args.push(arg);
+            args.push(arg).map_err(|_| Error::TooManyArgs)?;
         }
 
         if args.is_empty() {
-            return Err("No args")
+            return Err(Error::Empty)
         }
 
         Ok(Command { args })
@@ -29,14 +50,202 @@ impl<'a> Command<'a> {
     }
 }
 
-pub fn shell(prefix: &str) {
-    spin_sleep_ms(200);
+/// A command built into the shell, `run` gets every argument including the command name
+struct Builtin {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    run: fn(&[&str]),
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin { name: "help", usage: "help", help: "List the available commands", run: help },
+    Builtin { name: "echo", usage: "echo [args..]", help: "Print the arguments back", run: echo },
+    Builtin { name: "pinmux", usage: "pinmux", help: "Show the board pin-mux table", run: pinmux },
+    Builtin { name: "date", usage: "date [YYYY-MM-DDTHH:MM:SS | @secs]", help: "Show or set the UTC date & time", run: date },
+    Builtin { name: "uptime", usage: "uptime", help: "Show how long since the board powered on", run: uptime },
+    Builtin { name: "backtrace", usage: "backtrace", help: "Print the shell's call stack", run: backtrace },
+    Builtin { name: "sym", usage: "sym <hex address>", help: "Look up the function containing an address", run: sym },
+    Builtin { name: "reboot", usage: "reboot", help: "Reset the board", run: reboot },
+    Builtin { name: "poweroff", usage: "poweroff", help: "Halt the board until it's power cycled", run: poweroff },
+    Builtin { name: "watchdog", usage: "watchdog [start <secs> | stop]", help: "Show or control the watchdog", run: watchdog },
+];
+
+/// Read lines from the console and run them as commands, forever
+pub fn shell(prefix: &str) -> ! {
+    time::spin_sleep(Duration::from_millis(200));
     kprintln!("Welcome to my shell");
-    
-    let mut input_buf = [0 as u8; 512];
-    
+
+    let mut input_buf = [0 as u8; MAX_LINE];
+
+    loop {
+        kprint!("{}", prefix);
+
+        let line = recieve_line(&mut input_buf);
+        let mut args_buf = [""; MAX_ARGS];
+
+        match Command::parse(line, &mut args_buf) {
+            Ok(command) => run(&command),
+            Err(Error::Empty) => {},
+            Err(Error::TooManyArgs) => kprintln!("error: too many arguments"),
+        }
+    }
+}
+
+fn run(command: &Command) {
+    match BUILTINS.iter().find(|builtin| builtin.name == command.path()) {
+        Some(builtin) => (builtin.run)(&command.args),
+        None => kprintln!("unknown command: {}", command.path()),
+    }
+}
+
+/// Read a line of printable characters into `buf`, echoing as we go
+///
+/// Backspace/delete remove the last character, anything else unprintable or typed once the
+/// buffer is full rings the bell.
+fn recieve_line(buf: &mut [u8]) -> &str {
+    let mut line = StackVec::new(buf);
+
+    loop {
+        // The shell is the kernel's main loop, if we stop getting here the board should reset
+        WATCHDOG.feed();
+
+        // Wake up now and then even with nobody typing so the watchdog gets fed
+        let mut byte = [0];
+        let byte = match (&UART_CONSOLE).read_timeout(&mut byte, WATCHDOG_FEED_INTERVAL) {
+            Ok(1) => byte[0],
+            _ => continue,
+        };
+
+        match byte {
+            b'\r' | b'\n' => {
+                kprintln!();
+                break;
+            },
+            BACKSPACE | DELETE => match line.pop() {
+                Some(_) => kprint!("\x08 \x08"),
+                None => kprint!("{}", BELL as char),
+            },
+            b' '..=b'~' => match line.push(byte) {
+                Ok(()) => kprint!("{}", byte as char),
+                Err(_) => kprint!("{}", BELL as char),
+            },
+            _ => kprint!("{}", BELL as char),
+        }
+    }
+
+    // Only printable ASCII ever makes it into the line
+    str::from_utf8(line.into_slice()).unwrap_or("")
+}
+
+fn help(_args: &[&str]) {
+    for builtin in BUILTINS {
+        kprintln!("{: <36} {}", builtin.usage, builtin.help);
+    }
+}
+
+fn echo(args: &[&str]) {
+    for (i, arg) in args[1..].iter().enumerate() {
+        if i > 0 {
+            kprint!(" ");
+        }
+        kprint!("{}", arg);
+    }
+    kprintln!();
+}
+
+fn pinmux(_args: &[&str]) {
+    kprintln!("{: <5}{: <12}{: <8}{: <10}{: <8}{}", "pin", "peripheral", "signal", "function", "pull", "state");
+
+    for entry in pinmux::BOARD_PINMUX {
+        kprint!("{: <5}{: <12}{: <8}{: <10}{: <8}", entry.pin, entry.peripheral, entry.signal, entry.function, entry.pull);
+
+        match GPIO.function(entry.pin) {
+            Some(function) if function != entry.function => kprintln!("changed to {}", function),
+            _ if pinmux::is_applied(entry) => kprintln!("applied"),
+            _ => kprintln!("not applied"),
+        }
+    }
+}
+
+fn date(args: &[&str]) {
+    match args {
+        [_] => match wallclock::now() {
+            Some(now) => kprintln!("{}", now),
+            None => kprintln!("clock not set, use date YYYY-MM-DDTHH:MM:SS"),
+        },
+        [_, time] => {
+            let unix = match time.strip_prefix('@') {
+                Some(secs) => secs.parse().ok().map(Duration::from_secs),
+                None => DateTime::parse(time).map(|datetime| datetime.to_unix()),
+            };
+
+            match unix {
+                Some(unix) => {
+                    wallclock::set(unix);
+                    kprintln!("{}", DateTime::from_unix(unix));
+                },
+                None => kprintln!("date: invalid time '{}'", time),
+            }
+        },
+        _ => kprintln!("usage: date [YYYY-MM-DDTHH:MM:SS | @secs]"),
+    }
+}
+
+fn uptime(_args: &[&str]) {
+    match wallclock::now() {
+        Some(now) => kprintln!("{} up {}", now, wallclock::uptime()),
+        None => kprintln!("up {}", wallclock::uptime()),
+    }
+}
+
+fn reboot(_args: &[&str]) {
+    kprintln!("Rebooting");
+    UART_CONSOLE.flush();
+    WATCHDOG.reboot();
+}
+
+fn poweroff(_args: &[&str]) {
+    kprintln!("Powering off");
+    UART_CONSOLE.flush();
+    WATCHDOG.poweroff();
+}
+
+fn watchdog(args: &[&str]) {
+    match args {
+        [_] => {
+            match WATCHDOG.time_left() {
+                Some(left) => kprintln!("running, resets in {} unless fed", left),
+                None => kprintln!("stopped"),
+            }
+            kprintln!("last reset: {}", WATCHDOG.last_reset_reason());
+        },
+        [_, "start", secs] => match secs.parse() {
+            Ok(secs) => WATCHDOG.start(Duration::from_secs(secs)),
+            Err(_) => kprintln!("watchdog: invalid timeout '{}'", secs),
+        },
+        [_, "stop"] => WATCHDOG.stop(),
+        _ => kprintln!("usage: watchdog [start <secs> | stop]"),
+    }
+}
+
+fn backtrace(_args: &[&str]) {
+    for (i, address) in Backtrace::new().enumerate() {
+        kprintln!("{: >2}: {}", i, Symbolize(address));
+    }
 }
 
-fn recieve_line(buf:&[u8]) {
-    
-}
\ No newline at end of file
+fn sym(args: &[&str]) {
+    let address = match args {
+        [_, address] => u64::from_str_radix(address.trim_start_matches("0x"), 16),
+        _ => return kprintln!("usage: sym <hex address>"),
+    };
+
+    match address {
+        Ok(address) => match ksyms::lookup(address) {
+            Some(symbol) => kprintln!("{}", symbol),
+            None => kprintln!("no symbol for {:#x}", address),
+        },
+        Err(_) => kprintln!("sym: invalid address '{}'", args[1]),
+    }
+}
@@ -1,8 +1,143 @@
-// One day I will implement my own stack vector, for now I can just use the handy
-// StaticVec crate from /u/SlightlyOutOfPhase48 
+// A vector backed by a slice the caller provides, usually an array on the stack, so we get
+// push/pop without needing an allocator.
+//
+// Replaces the StaticVec crate from /u/SlightlyOutOfPhase48 I was using before
 // https://github.com/slightlyoutofphase/staticvec
 
-// pub struct StackVec {
-//     len: usize,
-//     data: 
-// }
\ No newline at end of file
+use core::ops::{Deref, DerefMut};
+
+pub struct StackVec<'a, T: 'a> {
+    storage: &'a mut [T],
+    len: usize,
+}
+
+impl<'a, T: 'a> StackVec<'a, T> {
+    /// Empty vector using `storage` as its backing store, capacity is `storage.len()`
+    pub fn new(storage: &'a mut [T]) -> StackVec<'a, T> {
+        StackVec {
+            storage,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.storage.len()
+    }
+
+    /// Append `value`, handing it back as the error if the vector is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+
+        self.storage[self.len] = value;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.storage[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.storage[..self.len]
+    }
+
+    pub fn into_slice(self) -> &'a mut [T] {
+        &mut self.storage[..self.len]
+    }
+}
+
+impl<'a, T: Clone + 'a> StackVec<'a, T> {
+    /// Remove and return the last element
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(self.storage[self.len].clone())
+    }
+}
+
+impl<'a, T: 'a> Deref for StackVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<'a, T: 'a> DerefMut for StackVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_until_full() {
+        let mut storage = [0u8; 3];
+        let mut vec = StackVec::new(&mut storage);
+        assert!(vec.is_empty());
+
+        for byte in b"abc" {
+            vec.push(*byte).unwrap();
+        }
+        assert!(vec.is_full());
+        assert_eq!(vec.push(b'd'), Err(b'd'));
+        assert_eq!(vec.len(), 3);
+        assert_eq!(&vec[..], b"abc");
+    }
+
+    #[test]
+    fn pop() {
+        let mut storage = [0u8; 3];
+        let mut vec = StackVec::new(&mut storage);
+        assert_eq!(vec.pop(), None);
+
+        vec.push(b'a').unwrap();
+        vec.push(b'b').unwrap();
+        assert_eq!(vec.pop(), Some(b'b'));
+        assert_eq!(vec.pop(), Some(b'a'));
+        assert_eq!(vec.pop(), None);
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn room_again_after_pop() {
+        // What backspace at the end of a full shell line does
+        let mut storage = [0u8; 2];
+        let mut vec = StackVec::new(&mut storage);
+        vec.push(b'a').unwrap();
+        vec.push(b'b').unwrap();
+        assert!(vec.push(b'c').is_err());
+
+        assert_eq!(vec.pop(), Some(b'b'));
+        vec.push(b'c').unwrap();
+        assert_eq!(vec.into_slice(), b"ac");
+    }
+
+    #[test]
+    fn deref() {
+        let mut storage = [""; 4];
+        let mut vec = StackVec::new(&mut storage);
+        vec.push("echo").unwrap();
+        vec.push("hi").unwrap();
+        assert_eq!(vec[0], "echo");
+        assert_eq!(vec.iter().count(), 2);
+        vec[1] = "there";
+        assert_eq!(vec.as_slice(), ["echo", "there"]);
+    }
+}
@@ -0,0 +1,97 @@
+// Monotonic clock, so callers stop passing raw microsecond counts around.
+//
+// `Instant` is a point on the ARM generic timer's system counter, which starts at zero when
+// the board powers on and never goes backwards. It's a plain 64 bit register read, so no
+// rollover games like the system timer's CLO/CHI pair. `Duration` lives in the library side
+// of the crate with the other hardware independent bits, see src/duration.rs.
+
+use crate::arch::{delay, timer};
+use core::{fmt, ops::{Add, AddAssign, Sub, SubAssign}};
+
+pub use pios::duration::Duration;
+
+/// A reading of the monotonic clock, only useful compared with other `Instant`s
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Instant {
+    since_boot: Duration,
+}
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant { since_boot: timer::uptime() }
+    }
+
+    /// Time since the counter started, i.e. since the board powered on
+    pub const fn since_boot(&self) -> Duration {
+        self.since_boot
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Time from `earlier` to `self`, zero if `earlier` is actually later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.since_boot.saturating_sub(earlier.since_boot)
+    }
+
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.since_boot.checked_sub(earlier.since_boot)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        Some(Instant { since_boot: self.since_boot.checked_add(duration)? })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        Some(Instant { since_boot: self.since_boot.checked_sub(duration)? })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        self.checked_sub(rhs).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Seconds since boot to the microsecond, e.g. `12.345678`
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:06}", self.since_boot.as_secs(), self.since_boot.subsec_micros())
+    }
+}
+
+/// Busy wait for at least `duration`
+pub fn spin_sleep(duration: Duration) {
+    delay::delay(duration);
+}
@@ -0,0 +1,75 @@
+// Kernel side of the timer wheel (see pios::wheel), lets any number of subsystems schedule
+// timeouts without each of them spinning on the system timer.
+//
+// The whole wheel is driven by a single one-shot alarm on system timer channel 3 which is
+// always programmed for the next slot that has something to do.
+
+use crate::arch::exception;
+use crate::pi::drivers::timer::{AlarmMode, Channel, SYSTEM_TIMER};
+use crate::syncro::{Lockable, NoLock};
+use pios::wheel::Wheel;
+
+pub use pios::wheel::{TimeoutCallback, TimeoutHandle};
+
+static WHEEL: NoLock<Wheel> = NoLock::new(Wheel::new());
+
+/// The wheel is shared with the alarm interrupt so IRQs are masked whenever we touch it
+fn with_wheel<R>(f: impl FnOnce(&mut Wheel) -> R) -> R {
+    exception::exec_with_irq_masked(|| WHEEL.lock(f))
+}
+
+/// Start the wheel at the current time, must be called before anything is scheduled
+pub fn init() {
+    let now = SYSTEM_TIMER.read();
+    with_wheel(|wheel| wheel.start_at(now));
+}
+
+/// Call `callback(data)` from the timer interrupt once `delay_us` microseconds have passed
+///
+/// Returns `None` if `MAX_TIMEOUTS` timeouts are already pending.
+pub fn schedule(delay_us: u64, callback: TimeoutCallback, data: usize) -> Option<TimeoutHandle> {
+    schedule_at(SYSTEM_TIMER.read() + delay_us, callback, data)
+}
+
+/// Call `callback(data)` from the timer interrupt once the system timer reaches `deadline_us`
+pub fn schedule_at(deadline_us: u64, callback: TimeoutCallback, data: usize) -> Option<TimeoutHandle> {
+    let handle = with_wheel(|wheel| wheel.insert(deadline_us, callback, data))?;
+    rearm();
+    Some(handle)
+}
+
+/// Cancel a pending timeout, returns false if it had already fired or been cancelled
+pub fn cancel(handle: TimeoutHandle) -> bool {
+    with_wheel(|wheel| wheel.cancel(handle))
+}
+
+/// Number of timeouts waiting to fire
+pub fn pending() -> usize {
+    with_wheel(|wheel| wheel.pending())
+}
+
+/// Fire everything that's due, for callers that have IRQs masked for a long time
+pub fn poll() {
+    let now = SYSTEM_TIMER.read();
+
+    // Callbacks are run with the wheel released so they can schedule & cancel timeouts
+    while let Some((callback, data)) = with_wheel(|wheel| wheel.advance(now)) {
+        callback(data);
+    }
+
+    rearm();
+}
+
+/// Program the alarm for the next time the wheel has work to do
+///
+/// The alarm is set with the wheel still held, otherwise an interrupt that schedules something
+/// sooner in between could have its alarm replaced by our later one.
+fn rearm() {
+    with_wheel(|wheel| match wheel.next_event(wheel.now()) {
+        Some(next) => {
+            let delay = next.saturating_sub(SYSTEM_TIMER.read()).min(u32::MAX as u64);
+            SYSTEM_TIMER.set_alarm(Channel::Three, delay as u32, AlarmMode::OneShot, poll);
+        },
+        None => SYSTEM_TIMER.cancel_alarm(Channel::Three),
+    })
+}
@@ -0,0 +1,77 @@
+// Wall clock time. The Pi has no RTC so it has to be told the time, either by the host at boot
+// or with the shell's `date` command, and from then on it's kept as an offset from the
+// monotonic clock. The date maths lives in pios::calendar.
+//
+// The host protocol is one line, `T` then the Unix time in decimal seconds then `\n` (or `\r`),
+// e.g. `T1700000000\n`. `make install` sends it once the kernel has gone over.
+//
+// Everything is UTC, there's no timezone database to do anything else with.
+
+use crate::{io::Read, pi::UART_CONSOLE, syncro::{Lockable, NoLock}, time::{Duration, Instant}};
+
+pub use pios::calendar::{DateTime, Uptime};
+
+/// Unix time when the monotonic clock read zero, `None` until someone tells us the time
+static EPOCH_OFFSET: NoLock<Option<Duration>> = NoLock::new(None);
+
+/// Set the wall clock to `unix`, a time since 1970-01-01T00:00:00Z
+pub fn set(unix: Duration) {
+    let offset = unix.saturating_sub(Instant::now().since_boot());
+    EPOCH_OFFSET.lock(|epoch| *epoch = Some(offset));
+}
+
+/// Current time since 1970-01-01T00:00:00Z, `None` if the clock was never set
+pub fn unix_now() -> Option<Duration> {
+    let offset = EPOCH_OFFSET.lock(|epoch| *epoch)?;
+    Some(offset.saturating_add(Instant::now().since_boot()))
+}
+
+/// Current UTC date & time, `None` if the clock was never set
+pub fn now() -> Option<DateTime> {
+    unix_now().map(DateTime::from_unix)
+}
+
+/// Time since the board powered on
+pub fn uptime() -> Uptime {
+    Uptime(Instant::now().since_boot())
+}
+
+/// Give the host `timeout` to send the time as `T<unix seconds>` and a newline
+///
+/// Meant for right after boot, when the serial loader is still attached and can send it
+/// straight after the kernel image. Anything else is thrown away. Returns true if the clock
+/// was set.
+pub fn receive_from_host(timeout: Duration) -> bool {
+    match read_host_time(Instant::now() + timeout) {
+        Some(secs) => {
+            set(Duration::from_secs(secs));
+            true
+        },
+        None => false,
+    }
+}
+
+fn read_host_time(deadline: Instant) -> Option<u64> {
+    // Each read only waits for what's left, the console's own timeout stays as it is
+    let read_byte = || {
+        let mut byte = [0];
+        let left = deadline.checked_duration_since(Instant::now())?;
+        match (&UART_CONSOLE).read_timeout(&mut byte, left) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    };
+
+    if read_byte()? != b'T' {
+        return None;
+    }
+
+    let mut secs: u64 = 0;
+    loop {
+        match read_byte()? {
+            byte @ b'0'..=b'9' => secs = secs.checked_mul(10)?.checked_add((byte - b'0') as u64)?,
+            b'\r' | b'\n' => return Some(secs),
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,414 @@
+// Hierarchical timer wheel, lets any number of subsystems schedule timeouts without each of
+// them spinning on the system timer. This is just the bookkeeping, times are plain
+// microsecond counts and nothing here touches the hardware. The kernel's timer_wheel module
+// owns one and drives it from a system timer alarm.
+//
+// Every timeout lives in one of `LEVELS` wheels of `SLOTS` slots. Level n slots are 64^n
+// microseconds wide, a timeout goes in the lowest level whose range covers its deadline and
+// gets cascaded down a level each time the wheel reaches its slot, until it fires from level 0.
+// Slots are doubly linked lists threaded through a fixed pool of nodes so scheduling and
+// cancelling are O(1) with no allocator.
+
+/// Most timeouts that can be pending at once
+pub const MAX_TIMEOUTS: usize = 4096;
+
+const LEVELS: usize = 6;
+const BITS: usize = 6;
+const SLOTS: usize = 1 << BITS;
+const MASK: u64 = SLOTS as u64 - 1;
+
+/// Furthest ahead the wheel can hold a timeout, about 19 hours. Anything later is parked in the
+/// top level and re-filed each time it comes round until it's in range.
+const MAX_DELAY: u64 = (1 << (BITS * LEVELS)) - 1;
+
+const NIL: u16 = u16::MAX;
+
+/// Called from the timer interrupt with the `data` passed to `schedule`
+pub type TimeoutCallback = fn(usize);
+
+/// Identifies a scheduled timeout so it can be cancelled
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeoutHandle {
+    index: u16,
+    generation: u16,
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    expires: u64,
+    callback: Option<TimeoutCallback>,
+    data: usize,
+    next: u16,
+    prev: u16,
+    /// Bumped each time the node is freed so old handles can't cancel whoever gets it next
+    generation: u16,
+    /// `level * SLOTS + slot` of the list the node is in, NIL if it's free
+    slot: u16,
+}
+
+impl Node {
+    const EMPTY: Node = Node {
+        expires: 0,
+        callback: None,
+        data: 0,
+        next: NIL,
+        prev: NIL,
+        generation: 0,
+        slot: NIL,
+    };
+}
+
+/// The wheel itself, it knows nothing about the hardware. Whoever owns it keeps it fed with the
+/// current time through `advance` and programs an alarm for `next_event`.
+pub struct Wheel {
+    nodes: [Node; MAX_TIMEOUTS],
+    heads: [u16; LEVELS * SLOTS],
+    /// Bit n of level l set if slot n of level l has anything in it
+    occupied: [u64; LEVELS],
+    /// Free list threaded through `next`
+    free: u16,
+    /// Nodes past here have never been used, saves building the free list up front
+    unused: u16,
+    /// Every timeout due before `now` has fired and every slot due at `now` has been cascaded
+    now: u64,
+    len: usize,
+}
+
+impl Default for Wheel {
+    fn default() -> Wheel {
+        Wheel::new()
+    }
+}
+
+impl Wheel {
+    pub const fn new() -> Wheel {
+        Wheel {
+            nodes: [Node::EMPTY; MAX_TIMEOUTS],
+            heads: [NIL; LEVELS * SLOTS],
+            occupied: [0; LEVELS],
+            free: NIL,
+            unused: 0,
+            now: 0,
+            len: 0,
+        }
+    }
+
+    /// Start the wheel at `now`, before anything is scheduled
+    pub fn start_at(&mut self, now: u64) {
+        self.now = now;
+    }
+
+    /// Every timeout due before this has fired
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Number of timeouts waiting to fire
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// File a timeout for `expires`, `None` if `MAX_TIMEOUTS` are already pending
+    pub fn insert(&mut self, expires: u64, callback: TimeoutCallback, data: usize) -> Option<TimeoutHandle> {
+        let index = if self.free != NIL {
+            let index = self.free;
+            self.free = self.nodes[index as usize].next;
+            index
+        } else if (self.unused as usize) < MAX_TIMEOUTS {
+            self.unused += 1;
+            self.unused - 1
+        } else {
+            return None;
+        };
+
+        let node = &mut self.nodes[index as usize];
+        node.expires = expires;
+        node.callback = Some(callback);
+        node.data = data;
+        let generation = node.generation;
+
+        self.link(index);
+        self.len += 1;
+
+        Some(TimeoutHandle { index, generation })
+    }
+
+    /// Returns false if the timeout had already fired or been cancelled
+    pub fn cancel(&mut self, handle: TimeoutHandle) -> bool {
+        let node = &self.nodes[handle.index as usize];
+        if node.generation != handle.generation || node.slot == NIL {
+            return false;
+        }
+
+        self.unlink(handle.index);
+        self.release(handle.index);
+        true
+    }
+
+    fn release(&mut self, index: u16) {
+        let node = &mut self.nodes[index as usize];
+        node.callback = None;
+        node.generation = node.generation.wrapping_add(1);
+        node.next = self.free;
+        self.free = index;
+        self.len -= 1;
+    }
+
+    /// File `index` into the right slot for its deadline relative to `now`
+    fn link(&mut self, index: u16) {
+        // Already late, fire on the next advance
+        let expires = self.nodes[index as usize].expires.max(self.now);
+        let delta = expires - self.now;
+
+        let (level, slot) = if delta < SLOTS as u64 {
+            (0, expires & MASK)
+        } else {
+            // Highest level whose slots are no wider than delta
+            let level = (63 - delta.leading_zeros() as usize) / BITS;
+            if level < LEVELS {
+                (level, (expires >> (BITS * level)) & MASK)
+            } else {
+                let level = LEVELS - 1;
+                (level, ((self.now + MAX_DELAY) >> (BITS * level)) & MASK)
+            }
+        };
+
+        let list = level * SLOTS + slot as usize;
+        let head = self.heads[list];
+
+        let node = &mut self.nodes[index as usize];
+        node.slot = list as u16;
+        node.prev = NIL;
+        node.next = head;
+
+        if head != NIL {
+            self.nodes[head as usize].prev = index;
+        }
+        self.heads[list] = index;
+        self.occupied[level] |= 1 << slot;
+    }
+
+    fn unlink(&mut self, index: u16) {
+        let Node { prev, next, slot, .. } = self.nodes[index as usize];
+        let list = slot as usize;
+
+        if prev != NIL {
+            self.nodes[prev as usize].next = next;
+        } else {
+            self.heads[list] = next;
+        }
+        if next != NIL {
+            self.nodes[next as usize].prev = prev;
+        }
+
+        if self.heads[list] == NIL {
+            self.occupied[list / SLOTS] &= !(1 << (list % SLOTS));
+        }
+        self.nodes[index as usize].slot = NIL;
+    }
+
+    /// Earliest time at or after `from` when a level 0 slot is due or a higher slot needs
+    /// cascading, `None` if the wheel is empty
+    pub fn next_event(&self, from: u64) -> Option<u64> {
+        let mut next: Option<u64> = None;
+
+        for (level, occupied) in self.occupied.iter().enumerate() {
+            if *occupied == 0 {
+                continue;
+            }
+
+            // First slot boundary on this level at or after `from`, and how many slots on
+            // from there the next occupied one is
+            let shift = BITS * level;
+            let base = (from + (1 << shift) - 1) >> shift;
+            let offset = occupied.rotate_right((base & MASK) as u32).trailing_zeros() as u64;
+            let time = (base + offset) << shift;
+
+            next = Some(next.map_or(time, |next| next.min(time)));
+        }
+
+        next
+    }
+
+    /// Move the now cascaded slot contents down a level
+    fn cascade(&mut self) {
+        for level in (1..LEVELS).rev() {
+            let shift = BITS * level;
+            if self.now & ((1 << shift) - 1) != 0 {
+                continue;
+            }
+
+            let list = level * SLOTS + ((self.now >> shift) & MASK) as usize;
+            let mut index = self.heads[list];
+            self.heads[list] = NIL;
+            self.occupied[level] &= !(1 << (list % SLOTS));
+
+            while index != NIL {
+                let next = self.nodes[index as usize].next;
+                self.link(index);
+                index = next;
+            }
+        }
+    }
+
+    /// Advance the wheel towards `target`, stopping at the first expired timeout
+    ///
+    /// Returns the callback of the expired timeout, which has already been removed from the
+    /// wheel, or `None` once everything due by `target` has been handed out.
+    pub fn advance(&mut self, target: u64) -> Option<(TimeoutCallback, usize)> {
+        loop {
+            let list = (self.now & MASK) as usize;
+            let index = self.heads[list];
+            if index != NIL {
+                let Node { callback, data, .. } = self.nodes[index as usize];
+                self.unlink(index);
+                self.release(index);
+                if let Some(callback) = callback {
+                    return Some((callback, data));
+                }
+                continue;
+            }
+
+            match self.next_event(self.now + 1) {
+                Some(next) if next <= target => {
+                    self.now = next;
+                    self.cascade();
+                },
+                _ => {
+                    self.now = self.now.max(target);
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(_: usize) {}
+
+    /// Everything that fires by `target`, in the order it fired
+    fn fire(wheel: &mut Wheel, target: u64) -> Vec<usize> {
+        let mut fired = Vec::new();
+        while let Some((_, data)) = wheel.advance(target) {
+            fired.push(data);
+        }
+        fired
+    }
+
+    fn wheel_at(now: u64) -> Box<Wheel> {
+        let mut wheel = Box::new(Wheel::new());
+        wheel.start_at(now);
+        wheel
+    }
+
+    #[test]
+    fn fires_in_deadline_order() {
+        let mut wheel = wheel_at(1000);
+        wheel.insert(1030, record, 3).unwrap();
+        wheel.insert(1010, record, 1).unwrap();
+        wheel.insert(1020, record, 2).unwrap();
+        assert_eq!(wheel.pending(), 3);
+
+        assert_eq!(fire(&mut wheel, 1009), []);
+        assert_eq!(fire(&mut wheel, 1020), [1, 2]);
+        assert_eq!(wheel.now(), 1020);
+        assert_eq!(fire(&mut wheel, 2000), [3]);
+        assert_eq!(wheel.pending(), 0);
+        assert_eq!(wheel.next_event(wheel.now()), None);
+    }
+
+    #[test]
+    fn late_timeout_fires_straight_away() {
+        let mut wheel = wheel_at(1000);
+        wheel.insert(500, record, 1).unwrap();
+        assert_eq!(wheel.next_event(wheel.now()), Some(1000));
+        assert_eq!(fire(&mut wheel, 1000), [1]);
+    }
+
+    #[test]
+    fn cascades_down_to_the_deadline() {
+        // One timeout on each level, none may fire a microsecond early however it got
+        // cascaded on the way down
+        let mut wheel = wheel_at(12345);
+        let deadlines: Vec<u64> = (0..LEVELS as u32).map(|level| 12345 + 3 * 64u64.pow(level) + 7).collect();
+        for (data, deadline) in deadlines.iter().enumerate() {
+            wheel.insert(*deadline, record, data).unwrap();
+        }
+
+        for (data, deadline) in deadlines.iter().enumerate() {
+            assert_eq!(fire(&mut wheel, deadline - 1), [], "timeout {} fired early", data);
+            assert_eq!(fire(&mut wheel, *deadline), [data]);
+        }
+        assert_eq!(wheel.pending(), 0);
+    }
+
+    #[test]
+    fn beyond_max_delay_is_refiled() {
+        let mut wheel = wheel_at(0);
+        let deadline = 3 * MAX_DELAY;
+        wheel.insert(deadline, record, 1).unwrap();
+
+        // Parked at the far end of the top level until it comes into range
+        let mut time = 0;
+        while let Some(next) = wheel.next_event(wheel.now()) {
+            assert!(next <= deadline);
+            assert_eq!(fire(&mut wheel, next), if next == deadline { vec![1] } else { vec![] });
+            time = next;
+        }
+        assert_eq!(time, deadline);
+    }
+
+    #[test]
+    fn next_event() {
+        let mut wheel = wheel_at(100);
+        assert_eq!(wheel.next_event(100), None);
+
+        // Level 0, due at its own deadline
+        wheel.insert(150, record, 0).unwrap();
+        assert_eq!(wheel.next_event(100), Some(150));
+
+        // Level 1 (64us slots): the wheel has to wake when the slot starts to cascade it, which
+        // is before the level 0 timeout
+        let handle = wheel.insert(100 + 64 * 3, record, 1).unwrap();
+        assert_eq!(wheel.next_event(100), Some(150));
+        wheel.insert(120, record, 2).unwrap();
+        assert_eq!(wheel.next_event(100), Some(120));
+
+        assert!(wheel.cancel(handle));
+        assert_eq!(fire(&mut wheel, 150), [2, 0]);
+        assert_eq!(wheel.next_event(wheel.now()), None);
+
+        // Slot boundary of a higher level, not the deadline itself
+        wheel.insert(150 + 64 * 64 * 2 + 5, record, 3).unwrap();
+        assert_eq!(wheel.next_event(150), Some(64 * 64 * 2));
+    }
+
+    #[test]
+    fn cancel() {
+        let mut wheel = wheel_at(0);
+        let first = wheel.insert(10, record, 1).unwrap();
+        let second = wheel.insert(10, record, 2).unwrap();
+        assert!(wheel.cancel(first));
+        assert!(!wheel.cancel(first));
+        assert_eq!(fire(&mut wheel, 10), [2]);
+        assert!(!wheel.cancel(second));
+
+        // The node gets reused, the old handle mustn't cancel the new timeout
+        let third = wheel.insert(20, record, 3).unwrap();
+        assert!(!wheel.cancel(second));
+        assert_ne!(third, second);
+        assert_eq!(fire(&mut wheel, 20), [3]);
+    }
+
+    #[test]
+    fn pool_runs_out() {
+        let mut wheel = wheel_at(0);
+        let handles: Vec<TimeoutHandle> = (0..MAX_TIMEOUTS).map(|n| wheel.insert(n as u64, record, n).unwrap()).collect();
+        assert!(wheel.insert(0, record, 0).is_none());
+        assert!(wheel.cancel(handles[7]));
+        assert!(wheel.insert(0, record, 0).is_some());
+    }
+}
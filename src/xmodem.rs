@@ -1,167 +1,322 @@
-use crate::console::{self, ConsoleError};
+use crate::io::{self, Write};
+use crate::duration::Duration;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
 
-type MResult<T> = core::result::Result<T, ModemError>;
+/// Payload of a `SOH` block
+pub const BLOCK_SIZE: usize = 128;
+/// Payload of a `STX` block, XMODEM-1K
+pub const BLOCK_SIZE_1K: usize = 1024;
 
-#[derive(Debug)]
-pub struct ModemError {
-    kind: ErrorKind,
+type MResult<T> = io::Result<T>;
+
+/// Errors are plain `io::Error`s, what went wrong with the transfer is in the kind
+pub type ModemError = io::Error;
+pub use crate::io::ErrorKind;
+
+/// How hard to try before giving up on a transfer, see `Xmodem::set_retry_policy`
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Attempts at each packet before giving up with `BrokenPipe`
+    pub max_retries: usize,
+    /// Times the receiver asks the sender to start before giving up with
+    /// `TimedOut`, and how many intervals the sender waits to be asked
+    pub start_attempts: usize,
+    /// How many of those ask for CRC mode before falling back to checksums
+    pub crc_attempts: usize,
+    /// Time between asking the sender to start
+    pub start_interval: Duration,
+    /// Longest wait for the start of a block, or the reply to one
+    pub block_timeout: Duration,
+    /// Longest wait for each byte within a block
+    pub byte_timeout: Duration,
 }
 
-impl ModemError {
-    fn new(kind: ErrorKind) -> Self {
-        Self {
-            kind
+impl RetryPolicy {
+    /// Roughly what the XMODEM spec suggests
+    pub const fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 10,
+            start_attempts: 20,
+            crc_attempts: 3,
+            start_interval: Duration::from_secs(3),
+            block_timeout: Duration::from_secs(10),
+            byte_timeout: Duration::from_secs(1),
         }
     }
+}
 
-    pub fn kind(&self) -> &ErrorKind {        
-        &self.kind
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
     }
 }
-#[derive(PartialEq, Debug)]
-pub enum ErrorKind {
-    Interrupted,
-    BrokenPipe,
-    ConnectionAborted,
-    InvalidData,
-    UnexpectedEof,
-    ConsoleError
+
+/// How a transfer is getting on, passed to the progress callback
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Progress {
+    /// Waiting for the other side to start the transfer
+    Waiting,
+    /// The other side has responded, packets are on their way
+    Started,
+    /// This many packets have gone through so far
+    Packet(usize),
+    /// A packet or the start of the transfer is being tried again because of
+    /// this, usually `Interrupted` for a bad checksum or `TimedOut`
+    Retry(ErrorKind),
+    /// End of transmission acknowledged
+    Done,
 }
 
-impl From<ConsoleError> for ModemError {
-    fn from(error:ConsoleError) -> Self {
-        ModemError{ kind: ErrorKind::ConsoleError }
-    }
+/// Called as a transfer goes, mustn't print to the console being transferred over
+pub type ProgressFn = fn(Progress);
+
+/// Progress callback that does nothing
+pub fn noop(_: Progress) {}
+
+/// How each packet is checked. The receiver picks by starting the transfer
+/// with `C` for CRC or `NAK` for a checksum.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    /// 8-bit sum of the data, original XMODEM
+    Checksum,
+    /// CRC-16/CCITT, XMODEM-CRC. Either side falls back to `Checksum` if the
+    /// other doesn't support it.
+    Crc16,
+}
+
+/// CRC-16/CCITT as XMODEM uses it, polynomial 0x1021 starting from 0
+pub(crate) fn crc16(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Errors where the packet should just be tried again
+fn retryable(error: &ModemError) -> bool {
+    matches!(error.kind(), ErrorKind::Interrupted | ErrorKind::TimedOut)
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
 }
 
 /// Implementation of the XMODEM protocol.
 pub struct Xmodem<R> {
     packet: u8,
     inner: R,
-    started: bool
+    started: bool,
+    mode: Mode,
+    use_1k: bool,
+    progress: ProgressFn,
+    packets: usize,
+    policy: RetryPolicy,
 }
 
 impl Xmodem<()> {
 
-    //Unlikely to ever need to transmit using Xmodem from the Pi, if I do I'll
-    //just reimplement it again when I'm not being lazy.
-
-
     /// Transmits `data` to the receiver `to` using the XMODEM protocol. If the
-    /// length of the total data yielded by `data` is not a multiple of 128
-    /// bytes, the data is padded with zeroes and sent to the receiver.
+    /// length of `data` is not a multiple of 128 bytes, the last packet is
+    /// padded with zeroes.
     ///
-    /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    // #[inline]
-    // pub fn transmit<R, W>(data: R, to: W) -> MResult<usize>
-    //     where W: console::Read + console::Write, R: console::Read
-    // {
-    //     Xmodem::transmit_with_progress(data, to)
-    // }
+    /// Returns the number of bytes of `data` written to `to`, excluding padding
+    /// zeroes.
+    #[inline]
+    pub fn transmit<W>(data: &[u8], to: W) -> MResult<usize>
+        where W: io::Read + io::Write
+    {
+        Xmodem::transmit_with_progress(data, to, noop)
+    }
 
     /// Transmits `data` to the receiver `to` using the XMODEM protocol. If the
-    /// length of the total data yielded by `data` is not a multiple of 128
-    /// bytes, the data is padded with zeroes and sent to the receiver.
+    /// length of `data` is not a multiple of 128 bytes, the last packet is
+    /// padded with zeroes.
+    ///
+    /// Each packet is tried up to `RetryPolicy::max_retries` times before
+    /// giving up with `BrokenPipe`.
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the transmission. See the [`Progress`] enum for more information.
     ///
-    /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    // pub fn transmit_with_progress<R, W>(mut data: R, to: W) -> MResult<usize>
-    //     where W: console::Read + console::Write, R: console::Read
-    // {
-    //     let mut transmitter = Xmodem::new(to);
-    //     let mut packet = [0u8; 128];
-    //     let mut written = 0;
-    //     'next_packet: loop {
-    //         let n = data.read_max(&mut packet)?;
-    //         packet[n..].iter_mut().for_each(|b| *b = 0);
-
-    //         if n == 0 {
-    //             transmitter.write_packet(&[])?;
-    //             return Ok(written);
-    //         }
-
-    //         for _ in 0..10 {
-    //             match transmitter.write_packet(&packet) {
-    //                 Err(ref e) if e.kind() == &ErrorKind::Interrupted => continue,
-    //                 Err(e) => return Err(e),
-    //                 Ok(_) => {
-    //                     written += n;
-    //                     continue 'next_packet;
-    //                 }
-    //             }
-    //         }
-
-    //         return Err(ModemError::new(ErrorKind::BrokenPipe));
-    //     }
-    // }
+    /// Returns the number of bytes of `data` written to `to`, excluding padding
+    /// zeroes.
+    pub fn transmit_with_progress<W>(data: &[u8], to: W, f: ProgressFn) -> MResult<usize>
+        where W: io::Read + io::Write
+    {
+        let mut transmitter = Xmodem::new(to);
+        transmitter.set_progress(f);
+        transmitter.transmit_from(data)
+    }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
     /// `into`. Returns the number of bytes read from `from`, a multiple of 128.
     #[inline]
     pub fn receive<R, W>(from: R, into: W) -> MResult<usize>
-       where R: console::Read + console::Write, W: console::Write
+       where R: io::Read + io::Write, W: io::Write
     {
-        Xmodem::receive_with_progress(from, into)
+        Xmodem::receive_with_progress(from, into, noop)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
     /// `into`. Returns the number of bytes read from `from`, a multiple of 128.
     ///
+    /// If writing to `into` fails, e.g. with `CapacityExceeded` when it's a
+    /// `MemCursor` that's too small, the transfer stops with that error.
+    ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the reception. See the [`Progress`] enum for more information.
-    pub fn receive_with_progress<R, W>(from: R, mut into: W) -> MResult<usize>
-       where R: console::Read + console::Write, W: console::Write
+    pub fn receive_with_progress<R, W>(from: R, into: W, f: ProgressFn) -> MResult<usize>
+       where R: io::Read + io::Write, W: io::Write
     {
         let mut receiver = Xmodem::new(from);
-        let mut packet = [0u8; 128];
+        receiver.set_progress(f);
+        receiver.receive_into(into)
+    }
+}
+
+impl<T: io::Read + io::Write> Xmodem<T> {
+    /// Returns a new `Xmodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving
+    /// (downloading) and sending (uploading).
+    ///
+    /// CRC mode is used if the other side supports it, otherwise checksums.
+    pub fn new(inner: T) -> Self {
+        Xmodem::new_with_mode(inner, Mode::Crc16)
+    }
+
+    /// Like `new`, but `Mode::Checksum` sticks to checksums even if the other
+    /// side could do CRC. For peers that claim CRC support and get it wrong.
+    pub fn new_with_mode(inner: T, mode: Mode) -> Self {
+        Xmodem {
+            packet: 1,
+            started: false,
+            inner,
+            mode,
+            use_1k: false,
+            progress: noop,
+            packets: 0,
+            policy: RetryPolicy::new(),
+        }
+    }
+
+    /// Change how long to wait and how often to retry, the default is
+    /// `RetryPolicy::new()`
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// Have `f` called as the transfer goes, see [`Progress`]
+    pub fn set_progress(&mut self, f: ProgressFn) {
+        self.progress = f;
+    }
+
+    /// Send 1024 byte blocks where there's enough data left to fill them.
+    /// Off by default since plain XMODEM receivers don't understand them, and
+    /// only used in CRC mode. Receiving always accepts both sizes.
+    pub fn set_1k_blocks(&mut self, enabled: bool) {
+        self.use_1k = enabled;
+    }
+
+    /// The mode in use, only settled once the transfer has started
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Sends all of `data`, see `Xmodem::transmit`
+    pub fn transmit_from(&mut self, data: &[u8]) -> MResult<usize> {
+        self.write_all(data)?;
+        self.finish()?;
+        Ok(data.len())
+    }
+
+    /// Ends the transmission, for after sending with `io::Write`
+    pub fn finish(&mut self) -> MResult<()> {
+        self.write_packet(&[])?;
+        Ok(())
+    }
+
+    /// Receives a whole transfer into `into`, see `Xmodem::receive`
+    pub fn receive_into<W: io::Write>(&mut self, mut into: W) -> MResult<usize> {
+        let mut packet = [0u8; BLOCK_SIZE_1K];
         let mut received = 0;
-        'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
-                    Err(ref e) if e.kind() == &ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(0) => break 'next_packet,
-                    Ok(n) => {
-                        received += n;
-                        into.write(&packet);
-                        continue 'next_packet;
-                    }
+        loop {
+            match self.receive_block(&mut packet)? {
+                0 => return Ok(received),
+                n => {
+                    received += n;
+                    into.write_all(&packet[..n])?;
                 }
             }
+        }
+    }
 
-            return Err(ModemError::new(ErrorKind::BrokenPipe));
+    /// `write_packet` until the receiver ACKs it, giving up with `BrokenPipe`
+    /// after `max_retries` NAKs or timeouts and cancelling the transfer
+    pub(crate) fn send_block(&mut self, block: &[u8]) -> MResult<()> {
+        for _ in 0..self.policy.max_retries {
+            match self.write_packet(block) {
+                Err(ref e) if retryable(e) => (self.progress)(Progress::Retry(*e.kind())),
+                Err(e) => return Err(e),
+                Ok(_) => return Ok(()),
+            }
         }
 
-        Ok(received)
+        self.write_byte(CAN)?;
+        Err(ModemError::new(ErrorKind::BrokenPipe))
     }
-}
 
-impl<T: console::Read + console::Write> Xmodem<T> {
-    /// Returns a new `Xmodem` instance with the internal reader/writer set to
-    /// `inner`. The returned instance can be used for both receiving
-    /// (downloading) and sending (uploading).
-    pub fn new(inner: T) -> Self {
-        Xmodem { packet: 1, started: false, inner}
+    /// `read_packet` until a block comes through intact, giving up with
+    /// `BrokenPipe` after `max_retries` bad or missing ones. Returns 0 at the
+    /// end of the transmission. A sender that never starts has already had
+    /// `start_attempts` chances so its `TimedOut` is passed straight on.
+    pub(crate) fn receive_block(&mut self, buf: &mut [u8]) -> MResult<usize> {
+        for _ in 0..self.policy.max_retries {
+            match self.read_packet(buf) {
+                Err(ref e) if retryable(e) && self.started => (self.progress)(Progress::Retry(*e.kind())),
+                result => return result,
+            }
+        }
+
+        Err(ModemError::new(ErrorKind::BrokenPipe))
+    }
+
+    /// Go back to waiting for the start of a transfer, expecting the first
+    /// block to be numbered `packet`. YMODEM does this for every file, with
+    /// the header as block 0.
+    pub(crate) fn restart(&mut self, packet: u8) {
+        self.started = false;
+        self.packet = packet;
+        self.packets = 0;
     }
 
-    /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
-    /// `true`, an error of `ConnectionAborted` is returned if the read byte is
-    /// `CAN`.
+    /// Reads a single byte from the inner I/O stream, waiting at most
+    /// `timeout`. If `abort_on_can` is `true`, an error of `ConnectionAborted`
+    /// is returned if the read byte is `CAN`.
     ///
     /// # Errors
     ///
-    /// Returns an error if reading from the inner stream fails or if
-    /// `abort_on_can` is `true` and the read byte is `CAN`.
-    fn read_byte(&mut self, abort_on_can: bool) -> MResult<u8> {
-        let byte = self.inner.read_byte()?;
+    /// Returns an error if reading from the inner stream fails or times out,
+    /// or if `abort_on_can` is `true` and the read byte is `CAN`.
+    fn read_byte(&mut self, timeout: Duration, abort_on_can: bool) -> MResult<u8> {
+        let mut buf = [0];
+        if self.inner.read_timeout(&mut buf, timeout)? == 0 {
+            return Err(ModemError::new(ErrorKind::UnexpectedEof));
+        }
+        let byte = buf[0];
 
         if abort_on_can && byte == CAN {
             return Err(ModemError::new(ErrorKind::ConnectionAborted));
@@ -176,25 +331,23 @@ impl<T: console::Read + console::Write> Xmodem<T> {
     ///
     /// Returns an error if writing to the inner stream fails.
     fn write_byte(&mut self, byte: u8) -> MResult<()> {
-        self.inner.write(&[byte]);
-
-        Ok(())
+        self.inner.write_all(&[byte])
     }
 
     /// Reads a single byte from the inner I/O stream and compares it to `byte`.
     /// If the bytes match, the byte is returned as an `Ok`. If they differ and
-    /// the read byte is not `CAN`, an error of `InvalidData` with the message
-    /// `expected` is returned. If they differ and the read byte is `CAN`, an
-    /// error of `ConnectionAborted` is returned. In either case, if they bytes
-    /// differ, a `CAN` byte is written out to the inner stream.
+    /// the read byte is not `CAN`, an error of `InvalidData` is returned. If
+    /// they differ and the read byte is `CAN`, an error of `ConnectionAborted`
+    /// is returned. In either case, if they bytes differ, a `CAN` byte is
+    /// written out to the inner stream.
     ///
     /// # Errors
     ///
     /// Returns an error if reading from the inner stream fails, if the read
     /// byte was not `byte`, if the read byte was `CAN` and `byte` is not `CAN`,
     /// or if writing the `CAN` byte failed on byte mismatch.
-    fn expect_byte_or_cancel(&mut self, byte: u8, expected: &'static str) -> MResult<u8> {
-        let read = self.inner.read_byte()?;
+    fn expect_byte_or_cancel(&mut self, byte: u8) -> MResult<u8> {
+        let read = self.read_byte(self.policy.block_timeout, false)?;
 
         if read != byte {
             self.write_byte(CAN)?;
@@ -207,36 +360,267 @@ impl<T: console::Read + console::Write> Xmodem<T> {
         Ok(byte)
     }
 
-    /// Reads a single byte from the inner I/O stream and compares it to `byte`.
-    /// If they differ, an error of `InvalidData` with the message `expected` is
-    /// returned. Otherwise the byte is returned. If `byte` is not `CAN` and the
-    /// read byte is `CAN`, a `ConnectionAborted` error is returned.
+    /// Reads (downloads) a single packet from the inner stream using the XMODEM
+    /// protocol. On success, returns the number of bytes read: 128 for a `SOH`
+    /// block, 1024 for a `STX` block, or 0 at the end of the transmission.
+    ///
+    /// If the sender sends the previous block again, because our `ACK` for it
+    /// got lost, it's acknowledged again and skipped.
+    ///
+    /// The progress callback is called with `Progress::Waiting` then
+    /// `Progress::Started` when reception for the first packet has started,
+    /// subsequently with `Progress::Packet` when a packet is received
+    /// successfully and `Progress::Done` at the end of the transmission.
     ///
     /// # Errors
     ///
-    /// Returns an error if reading from the inner stream fails, or if the read
-    /// byte was not `byte`. If the read byte differed and was `CAN`, an error
-    /// of `ConnectionAborted` is returned. Otherwise, the error kind is
-    /// `InvalidData`.
-    fn expect_byte(&mut self, byte: u8, expected: &'static str) -> MResult<u8> {
-        let read = self.inner.read_byte()?;
+    /// Returns an error if reading or writing to the inner stream fails at any
+    /// point. Also returns an error if the XMODEM protocol indicates an error.
+    /// In particular, an `InvalidData` error is returned when:
+    ///
+    ///   * The sender doesn't send a second `EOT` after the first.
+    ///   * The received packet number isn't the one expected or the one before.
+    ///
+    /// An error of kind `Interrupted` is returned if a packet checksum or CRC
+    /// fails, or the sender's first byte for a packet isn't `EOT`, `SOH` or
+    /// `STX`. The packet has been `NAK`ed and should be read again.
+    ///
+    /// An error of kind `TimedOut` is returned if a packet doesn't turn up
+    /// within `RetryPolicy::block_timeout` or stalls for longer than
+    /// `RetryPolicy::byte_timeout`. It has also been `NAK`ed. If the sender
+    /// never starts the transfer at all, `TimedOut` is returned after
+    /// `RetryPolicy::start_attempts` requests.
+    ///
+    /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
+    /// received when not expected.
+    ///
+    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`, or if
+    /// the sender starts a 1024 byte block and `buf.len() < 1024`. The
+    /// transfer is cancelled in the latter case, so pass a 1024 byte buffer if
+    /// the sender might use XMODEM-1K.
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> MResult<usize> {
+        if buf.len() < BLOCK_SIZE {
+            return Err(ModemError::new(ErrorKind::UnexpectedEof))
+        }
 
-        if read != byte {
-            if read == CAN {
-                return Err(ModemError::new(ErrorKind::ConnectionAborted));
+        loop {
+            let first = if self.started {
+                self.read_byte(self.policy.block_timeout, true)
+            } else {
+                self.start_receive()
+            };
+
+            let size = match first {
+                Ok(EOT) => return self.receive_eot(),
+                Ok(SOH) => BLOCK_SIZE,
+                Ok(STX) => BLOCK_SIZE_1K,
+                Ok(_) => {
+                    // Line noise, or the start of a block went missing. Let the
+                    // rest go by and ask again.
+                    self.purge();
+                    self.ask_again()?;
+                    return Err(ModemError::new(ErrorKind::Interrupted));
+                }
+                Err(ref e) if e.kind() == &ErrorKind::TimedOut && self.started => {
+                    self.ask_again()?;
+                    return Err(ModemError::new(ErrorKind::TimedOut));
+                }
+                Err(e) => return Err(e),
+            };
+            if buf.len() < size {
+                self.write_byte(CAN)?;
+                return Err(ModemError::new(ErrorKind::UnexpectedEof));
             }
-            return Err(ModemError::new(ErrorKind::InvalidData));
+
+            let number = match self.read_block(&mut buf[..size]) {
+                Ok(Some(number)) => number,
+                Ok(None) => {
+                    self.write_byte(NAK)?;
+                    return Err(ModemError::new(ErrorKind::Interrupted));
+                }
+                Err(ref e) if e.kind() == &ErrorKind::TimedOut => {
+                    self.write_byte(NAK)?;
+                    return Err(ModemError::new(ErrorKind::TimedOut));
+                }
+                Err(e) => return Err(e),
+            };
+
+            if number == self.packet.wrapping_sub(1) {
+                self.write_byte(ACK)?;
+                continue;
+            }
+            if number != self.packet {
+                self.write_byte(CAN)?;
+                return Err(ModemError::new(ErrorKind::InvalidData));
+            }
+
+            self.write_byte(ACK)?;
+            self.packet = self.packet.wrapping_add(1);
+            self.packets += 1;
+            (self.progress)(Progress::Packet(self.packets));
+            return Ok(size);
         }
+    }
 
-        Ok(byte)
+    /// Reads the rest of a block after its `SOH` or `STX`, the data going into
+    /// `buf`. Returns the block's number if it came through intact, otherwise
+    /// `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if the sender stops partway through.
+    fn read_block(&mut self, buf: &mut [u8]) -> MResult<Option<u8>> {
+        let timeout = self.policy.byte_timeout;
+        let number = self.read_byte(timeout, false)?;
+        let complement = self.read_byte(timeout, false)?;
+
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte(timeout, false)?;
+        }
+        let valid = match self.mode {
+            Mode::Checksum => self.read_byte(timeout, false)? == checksum(buf),
+            Mode::Crc16 => {
+                let high = self.read_byte(timeout, false)?;
+                let low = self.read_byte(timeout, false)?;
+                u16::from_be_bytes([high, low]) == crc16(0, buf)
+            }
+        };
+
+        if !valid || complement != 255 - number {
+            return Ok(None);
+        }
+        Ok(Some(number))
     }
 
-    /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// `NAK`s a block that never turned up. Before the first one a sender
+    /// that hasn't started yet takes that as asking for checksums, so we
+    /// switch to them too.
+    fn ask_again(&mut self) -> MResult<()> {
+        if self.packets == 0 {
+            self.mode = Mode::Checksum;
+        }
+        self.write_byte(NAK)
+    }
+
+    /// Answers an `EOT`. Some senders send a lone `EOT` as line noise so we
+    /// `NAK` it and only believe it when it comes again.
+    fn receive_eot(&mut self) -> MResult<usize> {
+        self.write_byte(NAK)?;
+        match self.expect_byte_or_cancel(EOT) {
+            Err(ref e) if e.kind() == &ErrorKind::TimedOut => {
+                // Ask again, it'll turn up as the start of the next "packet"
+                self.write_byte(NAK)?;
+                return Err(ModemError::new(ErrorKind::TimedOut));
+            }
+            result => result?,
+        };
+        self.write_byte(ACK)?;
+        (self.progress)(Progress::Done);
+        Ok(0)
+    }
+
+    /// Throws away anything the sender sends until it's been quiet for
+    /// `RetryPolicy::byte_timeout`, so our reply doesn't get lost in it
+    fn purge(&mut self) {
+        while self.read_byte(self.policy.byte_timeout, false).is_ok() {}
+    }
+
+    /// Asks the sender to start, with `C` a few times if we want CRC mode and
+    /// then with `NAK` for checksums, every `RetryPolicy::start_interval` until
+    /// it sends something back. Returns that first byte.
     ///
-    /// The progress callback is called with `Progress::Start` when reception
-    /// for the first packet has started and subsequently with
-    /// `Progress::Packet` when a packet is received successfully.
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if the sender never responds in
+    /// `RetryPolicy::start_attempts` requests, or any other error reading or
+    /// writing fails with.
+    fn start_receive(&mut self) -> MResult<u8> {
+        (self.progress)(Progress::Waiting);
+        for attempt in 0..self.policy.start_attempts {
+            if self.mode == Mode::Crc16 && attempt >= self.policy.crc_attempts {
+                self.mode = Mode::Checksum;
+            }
+
+            self.write_byte(if self.mode == Mode::Crc16 { CRC } else { NAK })?;
+            match self.read_byte(self.policy.start_interval, true) {
+                Err(ref e) if e.kind() == &ErrorKind::TimedOut => {
+                    (self.progress)(Progress::Retry(ErrorKind::TimedOut));
+                }
+                Err(e) => return Err(e),
+                Ok(byte) => {
+                    self.started = true;
+                    (self.progress)(Progress::Started);
+                    return Ok(byte);
+                }
+            }
+        }
+
+        Err(ModemError::new(ErrorKind::TimedOut))
+    }
+
+    /// Waits for the receiver to ask for the transfer to start, with `C` for
+    /// CRC mode or `NAK` for checksums. Anything else it sends before then is
+    /// line noise and ignored, as is `C` if we've been told to use checksums;
+    /// the receiver will give up on CRC and send `NAK` soon enough.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from the inner stream fails, an error of
+    /// kind `ConnectionAborted` if the receiver sends `CAN`, or `TimedOut` if
+    /// it hasn't asked after `RetryPolicy::start_attempts` start intervals.
+    fn wait_for_start(&mut self) -> MResult<()> {
+        (self.progress)(Progress::Waiting);
+        let mut attempts = 0;
+        while attempts < self.policy.start_attempts {
+            match self.read_byte(self.policy.start_interval, true) {
+                Ok(NAK) => self.mode = Mode::Checksum,
+                Ok(CRC) if self.mode == Mode::Crc16 => {},
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == &ErrorKind::TimedOut => {
+                    attempts += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            self.drain_start_requests()?;
+            (self.progress)(Progress::Started);
+            return Ok(());
+        }
+
+        Err(ModemError::new(ErrorKind::TimedOut))
+    }
+
+    /// Goes through any other start requests that queued up before we started
+    /// listening. If the receiver has given up on CRC since its first one it's
+    /// in checksum mode now, and one of them is a `NAK`.
+    fn drain_start_requests(&mut self) -> MResult<()> {
+        loop {
+            match self.read_byte(Duration::ZERO, true) {
+                Ok(NAK) => self.mode = Mode::Checksum,
+                Ok(_) => {},
+                Err(ref e) if e.kind() == &ErrorKind::TimedOut => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits for the receiver to start the transfer, if it hasn't already
+    fn start_transmit(&mut self) -> MResult<()> {
+        if !self.started {
+            self.wait_for_start()?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Sends (uploads) a single packet to the inner stream using the XMODEM
+    /// protocol. If `buf` is empty, end of transmission is sent. Users of this
+    /// interface should ensure that `write_packet(&[])` is called when data
+    /// transmission is complete. On success, returns the number of bytes
+    /// written.
+    ///
+    /// The progress callback is called with `Progress::Packet` when a packet
+    /// is acknowledged and `Progress::Done` when the end of transmission is.
     ///
     /// # Errors
     ///
@@ -244,150 +628,449 @@ impl<T: console::Read + console::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
-    ///   * The sender doesn't send a second `EOT` after the first.
-    ///   * The received packet numbers don't match the expected values.
+    ///   * The receiver doesn't respond to an `EOT` with `ACK` or `NAK`.
+    ///
+    /// An error of kind `BrokenPipe` is returned if none of
+    /// `RetryPolicy::max_retries` `EOT`s are `ACK`ed.
+    ///
+    /// Anything else the receiver sends while we wait for the `ACK` or `NAK`
+    /// of a packet is ignored.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// A 1024 byte `buf` goes as an XMODEM-1K `STX` block, make sure the
+    /// receiver can take them first.
+    ///
+    /// An error of kind `UnexpectedEof` is returned if `buf.len()` isn't 0, 128
+    /// or 1024. The caller pads the last packet.
     ///
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
-    pub fn read_packet(&mut self, buf: &mut [u8]) -> MResult<usize> {
-        let mut bytes_read = 0;
-        if buf.len() < 128 {
+    /// An error of kind `Interrupted` is returned if the receiver `NAK`s the
+    /// packet, or `TimedOut` if it doesn't answer within
+    /// `RetryPolicy::block_timeout`. Either way it should be sent again.
+    pub fn write_packet(&mut self, buf: &[u8]) -> MResult<usize> {
+        let mut bytes_written = 0;
+        if buf.len() != BLOCK_SIZE && buf.len() != BLOCK_SIZE_1K && !buf.is_empty() {
             return Err(ModemError::new(ErrorKind::UnexpectedEof))
         }
 
-        if !self.started {
-            self.write_byte(NAK)?;
-            self.started = true;
-            self.packet = 1;
-        }
+        self.start_transmit()?;
 
-        match self.read_byte(true) {
-            Ok(EOT) => {
-                self.write_byte(NAK)?;
-                self.expect_byte_or_cancel(EOT, "Expect second EOT")?;
-                self.write_byte(ACK)?;
-                return Ok(bytes_read);
+        if buf.is_empty() {
+            // Some receivers ACK the first EOT, others NAK it to make sure it
+            // wasn't line noise and want a second one. Either way keep sending
+            // them until one gets ACKed, in case one goes missing.
+            for _ in 0..self.policy.max_retries {
+                self.write_byte(EOT)?;
+                bytes_written += 1;
+                match self.read_byte(self.policy.block_timeout, true) {
+                    Ok(ACK) => {
+                        (self.progress)(Progress::Done);
+                        return Ok(bytes_written);
+                    }
+                    Ok(NAK) => {},
+                    Err(ref e) if e.kind() == &ErrorKind::TimedOut => {},
+                    Ok(_) => return Err(ModemError::new(ErrorKind::InvalidData)),
+                    Err(e) => return Err(e),
                 }
-            Ok(SOH) => {
-                self.expect_byte_or_cancel(self.packet, "Wrong packet number")?;
-                self.expect_byte_or_cancel(255-self.packet, "One complement incorrect")?;
-                
-                let mut checksum: u8 = 0;
-                for i in 0..128 {
-                    buf[i] = self.read_byte(false)?;
-                    let (n, _) = checksum.overflowing_add(buf[i]);
-                    checksum = n;
-                    bytes_read += 1;
+            }
+            return Err(ModemError::new(ErrorKind::BrokenPipe));
+        }
+
+        self.write_byte(if buf.len() == BLOCK_SIZE_1K { STX } else { SOH })?;
+        self.write_byte(self.packet)?;
+        self.write_byte(255-self.packet)?;
+        bytes_written += 3;
+
+        for b in buf {
+            self.write_byte(*b)?;
+            bytes_written += 1;
+        }
+        match self.mode {
+            Mode::Checksum => {
+                self.write_byte(checksum(buf))?;
+                bytes_written += 1;
+            }
+            Mode::Crc16 => {
+                for b in &crc16(0, buf).to_be_bytes() {
+                    self.write_byte(*b)?;
                 }
-                match self.read_byte(false) {
-                    Ok(checksum_byte) if checksum_byte == checksum => {
-                        self.write_byte(ACK)?;
-                        self.packet +=1;
-                        Ok(128)
-                    },
-                    _ => {
-                        self.write_byte(NAK)?;
-                        return Err(ModemError::new(ErrorKind::Interrupted))
-                    }
+                bytes_written += 2;
+            }
+        }
+
+        loop {
+            match self.read_byte(self.policy.block_timeout, true)? {
+                ACK => {
+                    self.packet = self.packet.wrapping_add(1);
+                    self.packets += 1;
+                    (self.progress)(Progress::Packet(self.packets));
+                    return Ok(bytes_written);
                 }
+                NAK => return Err(ModemError::new(ErrorKind::Interrupted)),
+                // A late `C` or `NAK` the receiver sent before it saw the
+                // start of the transfer, or noise
+                _ => continue,
             }
-            Ok(CAN) => Err(ModemError::new(ErrorKind::ConnectionAborted)),
-            Ok(_) => Err(ModemError::new(ErrorKind::InvalidData)),
-            Err(e) => Err(e),
         }
     }
-    // Sends (uploads) a single packet to the inner stream using the XMODEM
-    // protocol. If `buf` is empty, end of transmissions is sent. Users of this
-    // interface should ensure that `write_packet(&[])` is called when data
-    // transmission is complete. On success, returns the number of bytes
-    // written.
-    //
-    // The progress callback is called with `Progress::Waiting` before waiting
-    // for the receiver's `NAK`, `Progress::Start` when transmission of the
-    // first packet has started and subsequently with `Progress::Packet` when a
-    // packet is sent successfully.
-    //
-    // # Errors
-    //
-    // Returns an error if reading or writing to the inner stream fails at any
-    // point. Also returns an error if the XMODEM protocol indicates an error.
-    // In particular, an `InvalidData` error is returned when:
-    //
-    //   * The receiver's first byte isn't a `NAK`.
-    //   * The receiver doesn't respond with a `NAK` to the first `EOT`.
-    //   * The receiver doesn't respond with an `ACK` to the second `EOT`.
-    //   * The receiver responds to a complete packet with something besides
-    //     `ACK` or `NAK`.
-    //
-    // An error of kind `UnexpectedEof` is returned if `buf.len() < 128 &&
-    // buf.len() != 0`.
-    //
-    // An error of kind `ConnectionAborted` is returned if a `CAN` byte is
-    // received when not expected.
-    //
-    // An error of kind `Interrupted` is returned if a packet checksum fails.
-    // pub fn write_packet(&mut self, buf: &[u8]) -> MResult<usize> {
-    //     let mut bytes_written = 0;
-    //     if buf.len() < 128 && buf.len() != 0 {
-    //         return Err(io::Error::new(io::ErrorKind::UnexpectedEof,"invalid buf length"))
-    //     }
-
-    //     if !self.started {
-    //         (self.progress)(Progress::Waiting);
-    //         self.expect_byte(NAK,"expected starting NAK")?;
-    //         self.started = true;
-    //         self.packet = 1;
-    //         (self.progress)(Progress::Started);
-    //     }
-
-    //     if buf.is_empty() {
-    //         self.write_byte(EOT)?;
-    //         self.expect_byte(NAK, "expect NAK for EOT")?;
-    //         self.write_byte(EOT)?;
-    //         self.expect_byte(ACK, "expect ACK for second EOT")?;
-    //         bytes_written += 2;
-    //         return Ok(bytes_written);
-    //     }
-
-    //     self.write_byte(SOH)?;
-    //     self.write_byte(self.packet)?;
-    //     self.write_byte(255-self.packet)?;
-    //     bytes_written += 3;
-
-    //     let mut checksum: u8 = 0;
-    //     for b in buf {
-    //         self.write_byte(*b)?;
-    //         bytes_written += 1;
-    //         let (n, _) = checksum.overflowing_add(*b);
-    //         checksum = n;
-    //     }
-    //     self.write_byte(checksum)?;
-    //     bytes_written +=1;
-    //     match self.read_byte(true) {
-    //         Ok(ACK) => {
-    //             (self.progress)(Progress::Packet(self.packet));
-    //             self.packet += 1;
-    //             Ok(bytes_written)
-    //         }
-    //         Ok(NAK) => Err(io::Error::new(io::ErrorKind::Interrupted, "ACK expected for checksum")),
-    //         Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "No ACK or NAK for checksum")),
-    //         Err(e) => Err(e)
-    //     }
-    // }
-
-    // Flush this output stream, ensuring that all intermediately buffered
-    // contents reach their destination.
-    //
-    // # Errors
-    //
-    // It is considered an error if not all bytes could be written due to I/O
-    // errors or EOF being reached.
-//     pub fn flush(&mut self) -> MResult<()> {
-//         self.inner.flush()
-//     }
+}
+
+impl<T: io::Read + io::Write> io::Read for Xmodem<T> {
+    /// Receives the next block into `buf`, returning its size or 0 once the
+    /// sender's finished. `buf` has to hold a whole block, see `read_packet`.
+    /// Bad blocks are retried as `Xmodem::receive` does.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receive_block(buf)
+    }
+}
+
+impl<T: io::Read + io::Write> io::Write for Xmodem<T> {
+    /// Sends as much of `buf` as fits in a block, retrying it as
+    /// `Xmodem::transmit` does, and returns how much that was. A block that
+    /// isn't full gets padded with zeroes so only the last write should be
+    /// short. Call `finish` once everything has been written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Need to know the mode before picking block sizes
+        self.start_transmit()?;
+        // Finish off with small blocks once a big one would be mostly padding
+        let size = if self.use_1k && self.mode == Mode::Crc16 && buf.len() > BLOCK_SIZE_1K - BLOCK_SIZE {
+            BLOCK_SIZE_1K
+        } else {
+            BLOCK_SIZE
+        };
+
+        let mut packet = [0u8; BLOCK_SIZE_1K];
+        let chunk = &buf[..buf.len().min(size)];
+        packet[..chunk.len()].copy_from_slice(chunk);
+
+        self.send_block(&packet[..size])?;
+        Ok(chunk.len())
+    }
+
+    /// Every block is acknowledged before `write` returns, nothing to do
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::loopback::{self, End, Fault};
+    use crate::memory::MemWriter;
+    use crate::io::Read;
+
+    /// Short enough that the tests don't hang around, long enough for the other thread
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_retries: 10,
+        start_attempts: 20,
+        crc_attempts: 3,
+        start_interval: Duration::from_millis(20),
+        block_timeout: Duration::from_millis(200),
+        byte_timeout: Duration::from_millis(50),
+    };
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    /// Sends `data` from `sender` on another thread to `receiver`, returning
+    /// how each end got on and what the receiver ended up with
+    fn transfer(data: &[u8], mut sender: Xmodem<End>, mut receiver: Xmodem<End>)
+        -> (MResult<usize>, MResult<usize>, Vec<u8>)
+    {
+        sender.set_retry_policy(POLICY);
+        receiver.set_retry_policy(POLICY);
+
+        let data = data.to_vec();
+        let sending = thread::spawn(move || sender.transmit_from(&data));
+        let mut received = Vec::new();
+        let result = receiver.receive_into(&mut received);
+        // Let the sender notice we're gone before it gives up on its own
+        drop(receiver);
+
+        (sending.join().unwrap(), result, received)
+    }
+
+    fn padded(data: &[u8], block: usize) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        padded.resize(data.len().div_ceil(block) * block, 0);
+        padded
+    }
+
+    fn roundtrip(len: usize, mode: Mode, use_1k: bool, to_sender: &[Fault], to_receiver: &[Fault]) {
+        let (mut a, mut b) = loopback::pair();
+        for fault in to_sender { a = a.inject(*fault); }
+        for fault in to_receiver { b = b.inject(*fault); }
+
+        let mut sender = Xmodem::new_with_mode(b, mode);
+        sender.set_1k_blocks(use_1k);
+        let receiver = Xmodem::new_with_mode(a, mode);
+
+        let data = data(len);
+        let (sent, received, into) = transfer(&data, sender, receiver);
+        assert_eq!(sent.unwrap(), len);
+        assert_eq!(received.unwrap(), into.len());
+        assert_eq!(into, padded(&data, BLOCK_SIZE));
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(0, b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn roundtrip_checksum() {
+        roundtrip(1000, Mode::Checksum, false, &[], &[]);
+    }
+
+    #[test]
+    fn roundtrip_crc() {
+        roundtrip(1000, Mode::Crc16, false, &[], &[]);
+    }
+
+    #[test]
+    fn roundtrip_1k() {
+        // 1K blocks, then a small one to finish
+        roundtrip(2 * BLOCK_SIZE_1K + 100, Mode::Crc16, true, &[], &[]);
+    }
+
+    #[test]
+    fn roundtrip_empty() {
+        roundtrip(0, Mode::Crc16, false, &[], &[]);
+    }
+
+    #[test]
+    fn dropped_byte() {
+        roundtrip(300, Mode::Crc16, false, &[], &[Fault::Drop(40)]);
+    }
+
+    #[test]
+    fn dropped_block_start() {
+        roundtrip(300, Mode::Crc16, false, &[], &[Fault::Drop(0)]);
+    }
+
+    #[test]
+    fn corrupted_checksum() {
+        // SOH, number and complement, then the data, then the checksum
+        roundtrip(300, Mode::Checksum, false, &[], &[Fault::Corrupt(3 + BLOCK_SIZE)]);
+    }
+
+    #[test]
+    fn corrupted_crc() {
+        roundtrip(300, Mode::Crc16, false, &[], &[Fault::Corrupt(3 + BLOCK_SIZE + 1)]);
+    }
+
+    #[test]
+    fn corrupted_block_number() {
+        roundtrip(300, Mode::Crc16, false, &[], &[Fault::Corrupt(2)]);
+    }
+
+    #[test]
+    fn lost_ack_is_a_duplicate() {
+        roundtrip(300, Mode::Crc16, false, &[Fault::DropNth { byte: ACK, n: 0 }], &[]);
+    }
+
+    #[test]
+    fn lost_start_request() {
+        roundtrip(300, Mode::Crc16, false, &[Fault::Drop(0)], &[]);
+    }
+
+    #[test]
+    fn truncated_eot() {
+        // The second EOT goes missing, then the next one
+        roundtrip(300, Mode::Crc16, false, &[], &[Fault::DropNth { byte: EOT, n: 1 }]);
+        roundtrip(300, Mode::Crc16, false, &[], &[
+            Fault::DropNth { byte: EOT, n: 1 },
+            Fault::DropNth { byte: EOT, n: 2 },
+        ]);
+    }
+
+    #[test]
+    fn lost_eot_ack() {
+        // The receiver has everything and is gone, the sender can't know that
+        let (a, b) = loopback::pair();
+        let data = data(300);
+        let (sent, received, into) = transfer(
+            &data,
+            Xmodem::new(b),
+            Xmodem::new(a.inject(Fault::DropNth { byte: ACK, n: 3 })),
+        );
+        assert_eq!(sent.unwrap_err().kind(), &ErrorKind::BrokenPipe);
+        assert_eq!(received.unwrap(), into.len());
+        assert_eq!(into, padded(&data, BLOCK_SIZE));
+    }
+
+    #[test]
+    fn sender_cancels_mid_stream() {
+        let (a, b) = loopback::pair();
+        let (_, received, _) = transfer(
+            &data(1000),
+            Xmodem::new(b.inject(Fault::Cancel(BLOCK_SIZE * 3))),
+            Xmodem::new(a),
+        );
+        assert_eq!(received.unwrap_err().kind(), &ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn receiver_cancels_mid_stream() {
+        let (a, b) = loopback::pair();
+        // C, then ACK, ACK, CAN...
+        let (sent, received, _) = transfer(
+            &data(1000),
+            Xmodem::new(b),
+            Xmodem::new(a.inject(Fault::Cancel(3))),
+        );
+        assert_eq!(sent.unwrap_err().kind(), &ErrorKind::ConnectionAborted);
+        assert!(received.is_err());
+    }
+
+    #[test]
+    fn receiver_falls_back_to_checksum() {
+        let (a, b) = loopback::pair();
+        let mut sender = Xmodem::new_with_mode(b, Mode::Checksum);
+        let mut receiver = Xmodem::new(a);
+        sender.set_retry_policy(POLICY);
+        receiver.set_retry_policy(POLICY);
+
+        let data = data(300);
+        let sending = {
+            let data = data.clone();
+            thread::spawn(move || sender.transmit_from(&data))
+        };
+        let mut into = Vec::new();
+        receiver.receive_into(&mut into).unwrap();
+        assert_eq!(receiver.mode(), Mode::Checksum);
+        assert_eq!(sending.join().unwrap().unwrap(), data.len());
+        assert_eq!(into, padded(&data, BLOCK_SIZE));
+    }
+
+    #[test]
+    fn dead_line_gives_up() {
+        let (a, _b) = loopback::pair();
+        let mut receiver = Xmodem::new(a);
+        receiver.set_retry_policy(RetryPolicy { start_attempts: 2, ..POLICY });
+        let result = receiver.receive_into(io::Sink);
+        assert_eq!(result.unwrap_err().kind(), &ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn io_traits() {
+        let (a, b) = loopback::pair();
+        let mut sender = Xmodem::new(b);
+        let mut receiver = Xmodem::new(a);
+        sender.set_retry_policy(POLICY);
+        receiver.set_retry_policy(POLICY);
+
+        let data = data(300);
+        let sending = {
+            let data = data.clone();
+            thread::spawn(move || {
+                sender.write_all(&data)?;
+                sender.finish()
+            })
+        };
+
+        let mut into = Vec::new();
+        let mut block = [0u8; BLOCK_SIZE_1K];
+        loop {
+            match receiver.read(&mut block).unwrap() {
+                0 => break,
+                n => into.extend_from_slice(&block[..n]),
+            }
+        }
+        sending.join().unwrap().unwrap();
+        assert_eq!(into, padded(&data, BLOCK_SIZE));
+    }
+
+    #[test]
+    fn receive_into_too_small() {
+        let (a, b) = loopback::pair();
+        let mut sender = Xmodem::new(b);
+        let mut receiver = Xmodem::new(a);
+        sender.set_retry_policy(POLICY);
+        receiver.set_retry_policy(POLICY);
+
+        let data = data(300);
+        let sending = {
+            let data = data.clone();
+            thread::spawn(move || sender.transmit_from(&data))
+        };
+
+        // Room for two of the three blocks
+        let mut region = [0u8; 2 * BLOCK_SIZE];
+        let memory = unsafe { MemWriter::new(region.as_mut_ptr_range()) };
+        let result = receiver.receive_into(memory);
+        drop(receiver);
+        let _ = sending.join();
+
+        assert_eq!(result.unwrap_err().kind(), &ErrorKind::CapacityExceeded);
+        assert_eq!(region[..], data[..2 * BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn late_sender_uses_checksum() {
+        let (a, b) = loopback::pair();
+        let mut sender = Xmodem::new(b);
+        let mut receiver = Xmodem::new(a);
+        sender.set_retry_policy(POLICY);
+        receiver.set_retry_policy(POLICY);
+
+        let data = data(300);
+        let sending = {
+            let data = data.clone();
+            thread::spawn(move || {
+                // Long enough for the receiver to give up on CRC, with its Cs still queued
+                thread::sleep(std::time::Duration::from_millis(100));
+                let result = sender.transmit_from(&data);
+                (result, sender.mode())
+            })
+        };
+        let mut into = Vec::new();
+        receiver.receive_into(&mut into).unwrap();
+        let (sent, mode) = sending.join().unwrap();
+
+        assert_eq!(sent.unwrap(), data.len());
+        assert_eq!(mode, Mode::Checksum);
+        assert_eq!(receiver.mode(), Mode::Checksum);
+        assert_eq!(into, padded(&data, BLOCK_SIZE));
+    }
+
+    #[test]
+    fn noise_before_first_block() {
+        let (a, mut b) = loopback::pair();
+        let mut receiver = Xmodem::new(a);
+        receiver.set_retry_policy(POLICY);
+
+        let data = data(300);
+        let sending = {
+            let data = data.clone();
+            thread::spawn(move || {
+                // Noise on the line as the receiver asks for CRC, it NAKs that
+                let mut request = [0];
+                b.read(&mut request).unwrap();
+                assert_eq!(request[0], CRC);
+                b.write_all(&[0x55]).unwrap();
+
+                let mut sender = Xmodem::new(b);
+                sender.set_retry_policy(POLICY);
+                let result = sender.transmit_from(&data);
+                (result, sender.mode())
+            })
+        };
+        let mut into = Vec::new();
+        receiver.receive_into(&mut into).unwrap();
+        let (sent, mode) = sending.join().unwrap();
+
+        assert_eq!(sent.unwrap(), data.len());
+        assert_eq!(mode, Mode::Checksum);
+        assert_eq!(receiver.mode(), Mode::Checksum);
+        assert_eq!(into, padded(&data, BLOCK_SIZE));
+    }
 }
@@ -0,0 +1,369 @@
+// YMODEM batch transfers, built on the XMODEM code.
+//
+// Each file starts with a block numbered 0 holding its name, exact size and modification time,
+// followed by the data as an ordinary XMODEM-CRC transfer. The receiver asks for every header
+// and every data transfer with a fresh `C`. An empty name in block 0 ends the batch.
+//
+// Knowing the size means we can drop the padding on the last block instead of writing it over
+// whatever follows the kernel.
+
+use core::{fmt, str};
+use crate::io;
+use crate::xmodem::{ErrorKind, ModemError, ProgressFn, Xmodem, BLOCK_SIZE, BLOCK_SIZE_1K};
+
+type MResult<T> = io::Result<T>;
+
+/// Longest file name kept from a header, longer ones are cut short
+pub const MAX_NAME: usize = 128;
+
+/// What the sender told us about a file before sending it
+#[derive(Clone)]
+pub struct FileInfo {
+    name: [u8; MAX_NAME],
+    name_len: usize,
+    /// Exact length in bytes, if the sender gave one
+    pub size: Option<u64>,
+    /// Modification time in seconds since the Unix epoch, if the sender gave one
+    pub mtime: Option<u64>,
+}
+
+impl FileInfo {
+    pub fn name(&self) -> &str {
+        // Only ever filled from a whole &str cut on a char boundary
+        str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+
+    /// Parses a header block: `name NUL size [mtime [mode ...]] NUL` padded with zeroes, size
+    /// in decimal and the rest in octal. `None` for the empty header that ends a batch.
+    pub(crate) fn parse(block: &[u8]) -> MResult<Option<FileInfo>> {
+        let invalid = || ModemError::new(ErrorKind::InvalidData);
+
+        let name_end = block.iter().position(|&b| b == 0).ok_or_else(invalid)?;
+        if name_end == 0 {
+            return Ok(None);
+        }
+        let name = str::from_utf8(&block[..name_end]).map_err(|_| invalid())?;
+
+        let rest = &block[name_end + 1..];
+        let fields_end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        let mut fields = str::from_utf8(&rest[..fields_end]).unwrap_or("")
+            .split(' ')
+            .filter(|field| !field.is_empty());
+        let size = fields.next().and_then(|field| field.parse().ok());
+        // Plenty of senders put 0 here when they don't know
+        let mtime = fields.next()
+            .and_then(|field| u64::from_str_radix(field, 8).ok())
+            .filter(|&mtime| mtime != 0);
+
+        let mut name_len = name.len().min(MAX_NAME);
+        while !name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+        let mut info = FileInfo { name: [0; MAX_NAME], name_len, size, mtime };
+        info.name[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+
+        Ok(Some(info))
+    }
+}
+
+impl fmt::Debug for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileInfo")
+            .field("name", &self.name())
+            .field("size", &self.size)
+            .field("mtime", &self.mtime)
+            .finish()
+    }
+}
+
+/// Writes a header block for `name` into `block`, returning how much of it to send: 128 bytes
+/// if it fits, 1024 if not
+fn header(block: &mut [u8; BLOCK_SIZE_1K], name: &str, size: usize, mtime: Option<u64>) -> MResult<usize> {
+    if name.is_empty() || name.len() >= BLOCK_SIZE_1K {
+        return Err(ModemError::new(ErrorKind::InvalidData));
+    }
+
+    block.iter_mut().for_each(|b| *b = 0);
+    block[..name.len()].copy_from_slice(name.as_bytes());
+
+    let mut fields = Fields { buf: &mut block[name.len() + 1..], len: 0 };
+    let written = match mtime {
+        Some(mtime) => fmt::Write::write_fmt(&mut fields, format_args!("{} {:o}", size, mtime)),
+        None => fmt::Write::write_fmt(&mut fields, format_args!("{}", size)),
+    };
+    // Leave at least one zero after the fields to terminate them
+    if written.is_err() || name.len() + 1 + fields.len >= BLOCK_SIZE_1K {
+        return Err(ModemError::new(ErrorKind::InvalidData));
+    }
+
+    Ok(if name.len() + 1 + fields.len < BLOCK_SIZE { BLOCK_SIZE } else { BLOCK_SIZE_1K })
+}
+
+/// Formats the header fields straight into the block
+struct Fields<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for Fields<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for the next file's header
+    Header,
+    /// Got a header, its data is next
+    Data { size: Option<u64> },
+    /// The sender ended the batch
+    Done,
+}
+
+/// Receives a batch of files. Call `next_file` to get each file's details, then
+/// `receive_file` to put its data somewhere:
+///
+/// ```ignore
+/// let mut receiver = ymodem::Receiver::new(&UART_CONSOLE);
+/// while let Some(file) = receiver.next_file()? {
+///     kprintln!("{} ({:?} bytes)", file.name(), file.size);
+///     receiver.receive_file(memory)?;
+/// }
+/// ```
+pub struct Receiver<T> {
+    xmodem: Xmodem<T>,
+    state: State,
+}
+
+impl<T: io::Read + io::Write> Receiver<T> {
+    pub fn new(inner: T) -> Self {
+        Receiver { xmodem: Xmodem::new(inner), state: State::Header }
+    }
+
+    /// Have `f` called as each header and file goes, see `xmodem::Progress`
+    pub fn set_progress(&mut self, f: ProgressFn) {
+        self.xmodem.set_progress(f);
+    }
+
+    /// Waits for the sender to announce the next file. Returns `None` once the
+    /// batch is over. If the previous file's data was never asked for with
+    /// `receive_file` it gets thrown away first.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from the underlying XMODEM transfer, or an error of
+    /// kind `InvalidData` if the header block can't be made sense of.
+    pub fn next_file(&mut self) -> MResult<Option<FileInfo>> {
+        match self.state {
+            State::Done => return Ok(None),
+            State::Data { .. } => { self.receive_file(io::Sink)?; },
+            State::Header => {},
+        }
+
+        let mut block = [0u8; BLOCK_SIZE_1K];
+        self.xmodem.restart(0);
+        let received = self.xmodem.receive_block(&mut block)?;
+        if received == 0 {
+            // EOT where a header should be
+            return Err(ModemError::new(ErrorKind::InvalidData));
+        }
+
+        match FileInfo::parse(&block[..received])? {
+            Some(info) => {
+                self.state = State::Data { size: info.size };
+                Ok(Some(info))
+            }
+            None => {
+                self.state = State::Done;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Receives the data of the file `next_file` just announced into `into`.
+    /// If the header gave a size, padding on the last block is dropped and
+    /// exactly that many bytes are written. Otherwise everything is, padding
+    /// and all.
+    ///
+    /// Returns the number of bytes written to `into`, 0 if there's no file
+    /// waiting to be received.
+    pub fn receive_file<W: io::Write>(&mut self, mut into: W) -> MResult<usize> {
+        let size = match self.state {
+            State::Data { size } => size,
+            _ => return Ok(0),
+        };
+        self.state = State::Header;
+
+        let mut block = [0u8; BLOCK_SIZE_1K];
+        let mut written: u64 = 0;
+        self.xmodem.restart(1);
+        loop {
+            let received = self.xmodem.receive_block(&mut block)?;
+            if received == 0 {
+                return Ok(written as usize);
+            }
+
+            let keep = match size {
+                Some(size) => size.saturating_sub(written).min(received as u64) as usize,
+                None => received,
+            };
+            into.write_all(&block[..keep])?;
+            written += keep as u64;
+        }
+    }
+}
+
+/// Sends a batch of files, one `send_file` each and then `finish`
+pub struct Sender<T> {
+    xmodem: Xmodem<T>,
+}
+
+impl<T: io::Read + io::Write> Sender<T> {
+    /// Uses 1K blocks, every YMODEM receiver should take them
+    pub fn new(inner: T) -> Self {
+        let mut xmodem = Xmodem::new(inner);
+        xmodem.set_1k_blocks(true);
+        Sender { xmodem }
+    }
+
+    /// Have `f` called as each header and file goes, see `xmodem::Progress`
+    pub fn set_progress(&mut self, f: ProgressFn) {
+        self.xmodem.set_progress(f);
+    }
+
+    /// Sends `data` as a file called `name`, waiting for the receiver to ask
+    /// for it. Returns the number of bytes of `data` sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if `name` is empty or too long to
+    /// fit in a header, otherwise any error from the XMODEM transfer.
+    pub fn send_file(&mut self, name: &str, mtime: Option<u64>, data: &[u8]) -> MResult<usize> {
+        let mut block = [0u8; BLOCK_SIZE_1K];
+        let len = header(&mut block, name, data.len(), mtime)?;
+
+        self.xmodem.restart(0);
+        self.xmodem.send_block(&block[..len])?;
+
+        self.xmodem.restart(1);
+        self.xmodem.transmit_from(data)
+    }
+
+    /// Tells the receiver there are no more files
+    pub fn finish(mut self) -> MResult<()> {
+        self.xmodem.restart(0);
+        self.xmodem.send_block(&[0; BLOCK_SIZE])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::loopback;
+
+    fn parse(block: &[u8]) -> FileInfo {
+        FileInfo::parse(block).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parse_header() {
+        let info = parse(b"kernel8.img\x001234 14114121562 100644\0\0\0");
+        assert_eq!(info.name(), "kernel8.img");
+        assert_eq!(info.size, Some(1234));
+        assert_eq!(info.mtime, Some(0o14114121562));
+
+        let info = parse(b"kernel8.img\x001234\0\0\0");
+        assert_eq!((info.size, info.mtime), (Some(1234), None));
+
+        // A 0 mtime means the sender didn't know
+        let info = parse(b"kernel8.img\x001234 0\0");
+        assert_eq!((info.size, info.mtime), (Some(1234), None));
+
+        let info = parse(b"kernel8.img\0\0\0\0");
+        assert_eq!((info.size, info.mtime), (None, None));
+    }
+
+    #[test]
+    fn end_of_batch() {
+        assert!(FileInfo::parse(&[0; BLOCK_SIZE]).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_header() {
+        let error = FileInfo::parse(b"kernel\xff.img\x001234\0").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::InvalidData);
+        // Name never ends
+        assert_eq!(FileInfo::parse(b"kernel8.img").unwrap_err().kind(), &ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn long_name_is_cut_short() {
+        let mut block = vec![b'a'; MAX_NAME + 50];
+        block.extend_from_slice(b"\x0010\0");
+        assert_eq!(parse(&block).name(), "a".repeat(MAX_NAME));
+
+        // Cut before a character that would straddle the end
+        let mut block = vec![b'a'; MAX_NAME - 1];
+        block.extend_from_slice("é\x0010\0".as_bytes());
+        assert_eq!(parse(&block).name(), "a".repeat(MAX_NAME - 1));
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let mut block = [0u8; BLOCK_SIZE_1K];
+        assert_eq!(header(&mut block, "kernel8.img", 1234, Some(0o777)).unwrap(), BLOCK_SIZE);
+        let info = parse(&block);
+        assert_eq!((info.name(), info.size, info.mtime), ("kernel8.img", Some(1234), Some(0o777)));
+
+        // Too long for a 128 byte block
+        let name = "a".repeat(200);
+        assert_eq!(header(&mut block, &name, 1, None).unwrap(), BLOCK_SIZE_1K);
+        assert_eq!(parse(&block).size, Some(1));
+
+        assert_eq!(header(&mut block, "", 1, None).unwrap_err().kind(), &ErrorKind::InvalidData);
+        let name = "a".repeat(BLOCK_SIZE_1K - 2);
+        assert_eq!(header(&mut block, &name, 1234, None).unwrap_err().kind(), &ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn batch() {
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("kernel8.img", (0..3000).map(|i| (i * 7) as u8).collect()),
+            ("config.txt", b"arm_64bit=1\n".to_vec()),
+        ];
+
+        let (a, b) = loopback::pair();
+        let to_send = files.clone();
+        let sending = thread::spawn(move || {
+            let mut sender = Sender::new(b);
+            for (name, data) in &to_send {
+                assert_eq!(sender.send_file(name, Some(0o14114121562), data).unwrap(), data.len());
+            }
+            sender.finish().unwrap();
+        });
+
+        let mut receiver = Receiver::new(a);
+        for (name, data) in &files {
+            let info = receiver.next_file().unwrap().unwrap();
+            assert_eq!(info.name(), *name);
+            assert_eq!(info.size, Some(data.len() as u64));
+
+            // No padding past the size the header gave
+            let mut received = Vec::new();
+            assert_eq!(receiver.receive_file(&mut received).unwrap(), data.len());
+            assert_eq!(&received, data);
+        }
+        assert!(receiver.next_file().unwrap().is_none());
+        assert!(receiver.next_file().unwrap().is_none());
+
+        sending.join().unwrap();
+    }
+}
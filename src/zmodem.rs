@@ -0,0 +1,686 @@
+// ZMODEM receiver, so `sz` from a terminal program can stream a kernel without stopping to wait
+// for an ACK on every block like XMODEM does.
+//
+// Frames are a header (a type and 4 bytes of position or flags) optionally followed by data
+// subpackets. We only ever send hex headers; the sender's binary headers and data have any
+// awkward bytes escaped with ZDLE and carry a CRC-16 or, if we say we can take it, a CRC-32.
+// A session goes:
+//
+//     sz: ZRQINIT             rz: ZRINIT
+//     sz: ZFILE + header      rz: ZRPOS 0
+//     sz: ZDATA 0 + data ...
+//     sz: ZEOF <size>         rz: ZRINIT          (then ZFILE again for the next file)
+//     sz: ZFIN                rz: ZFIN
+//     sz: "OO"
+//
+// If a subpacket arrives corrupted we send ZRPOS with how far we've got and the sender rewinds
+// and carries on from there, so the caller's writer only ever sees good data, in order.
+//
+// Every read gives up after `TIMEOUT` so a dropped frame doesn't hang us, whatever timeout the
+// stream has.
+
+use crate::io;
+use crate::duration::Duration;
+use crate::xmodem::{crc16, ErrorKind, ModemError};
+use crate::ymodem::FileInfo;
+
+type MResult<T> = io::Result<T>;
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// Header formats, after ZPAD ZDLE
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+
+// Subpacket ends, after ZDLE
+/// End of frame, a header follows
+const ZCRCE: u8 = b'h';
+/// More data follows, no reply wanted
+const ZCRCG: u8 = b'i';
+/// More data follows, ZACK wanted
+const ZCRCQ: u8 = b'j';
+/// End of frame, ZACK wanted
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// ZRINIT flags
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+
+/// `sz` won't send bigger subpackets unless asked for 8K ones
+const MAX_SUBPACKET: usize = 1024;
+
+/// Bad or missing frames in a row before giving up
+const MAX_RETRIES: usize = 10;
+
+/// Longest the sender can go quiet before we nudge it, what `rz` uses
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Consecutive CANs that mean the sender has given up
+const CANCEL_COUNT: usize = 5;
+
+/// CRC-32 as ZMODEM uses it (the usual IEEE one), without the inversion at each end
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |mut crc, &byte| {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+        crc
+    })
+}
+
+#[derive(Clone, Copy)]
+struct Header {
+    kind: u8,
+    /// ZP0..ZP3, or ZF3..ZF0 for flags
+    data: [u8; 4],
+}
+
+impl Header {
+    fn with_position(kind: u8, position: u32) -> Header {
+        Header { kind, data: position.to_le_bytes() }
+    }
+
+    fn with_flags(kind: u8, flags: u8) -> Header {
+        Header { kind, data: [0, 0, 0, flags] }
+    }
+
+    fn position(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+}
+
+/// A byte from inside a binary header or subpacket
+enum Escaped {
+    Data(u8),
+    /// ZDLE followed by one of the subpacket ends
+    End(u8),
+}
+
+fn invalid() -> ModemError {
+    ModemError::new(ErrorKind::InvalidData)
+}
+
+/// Worth asking the sender again after, rather than giving up
+fn recoverable(error: &ModemError) -> bool {
+    matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::InvalidData | ErrorKind::Interrupted)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for the next ZFILE
+    Header,
+    /// Got a ZFILE, haven't asked for its data yet
+    Data,
+    /// Sender sent ZFIN
+    Done,
+}
+
+/// Receives a batch of files, used the same way as `ymodem::Receiver`: `next_file`
+/// for each file's details, then `receive_file` for its data.
+pub struct Receiver<T> {
+    inner: T,
+    state: State,
+    /// Whether the subpackets in the current frame have a CRC-32, decided by
+    /// the header before them
+    crc32: bool,
+}
+
+impl<T: io::Read + io::Write> Receiver<T> {
+    pub fn new(inner: T) -> Self {
+        Receiver { inner, state: State::Header, crc32: false }
+    }
+
+    /// Waits for the sender to announce the next file. Returns `None` once the
+    /// sender has finished. If the previous file's data was never asked for
+    /// with `receive_file` the sender is told to skip it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `ConnectionAborted` if the sender cancels, or
+    /// of the kind of the last problem if `MAX_RETRIES` frames in a row are
+    /// missing or corrupt.
+    pub fn next_file(&mut self) -> MResult<Option<FileInfo>> {
+        match self.state {
+            State::Done => return Ok(None),
+            State::Data => self.send_header(Header::with_position(ZSKIP, 0))?,
+            State::Header => {},
+        }
+
+        let ready = Header::with_flags(ZRINIT, CANFDX | CANOVIO | CANFC32);
+        let mut buf = [0u8; MAX_SUBPACKET];
+        let mut retries = 0;
+
+        self.send_header(ready)?;
+        loop {
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ref e) if recoverable(e) && retries < MAX_RETRIES => {
+                    retries += 1;
+                    self.send_header(ready)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            match header.kind {
+                ZFILE => match self.read_subpacket(&mut buf) {
+                    Ok((len, _)) => {
+                        let info = FileInfo::parse(&buf[..len])?.ok_or_else(invalid)?;
+                        self.state = State::Data;
+                        return Ok(Some(info));
+                    }
+                    Err(ref e) if recoverable(e) && retries < MAX_RETRIES => {
+                        retries += 1;
+                        self.send_header(Header::with_position(ZNAK, 0))?;
+                    }
+                    Err(e) => return Err(e),
+                },
+                // Only carries the sender's attention string, which we don't use
+                ZSINIT => {
+                    if self.read_subpacket(&mut buf).is_ok() {
+                        self.send_header(Header::with_position(ZACK, 0))?;
+                    }
+                }
+                ZFIN => {
+                    self.send_header(Header::with_position(ZFIN, 0))?;
+                    // "Over and out", read it so it doesn't turn up as input later
+                    for _ in 0..2 {
+                        if !matches!(self.read_byte(), Ok(b'O')) {
+                            break;
+                        }
+                    }
+                    self.state = State::Done;
+                    return Ok(None);
+                }
+                ZRQINIT => self.send_header(ready)?,
+                // Anything else, e.g. leftovers from the last file, means the
+                // sender hasn't heard us yet
+                _ => self.send_header(ready)?,
+            }
+        }
+    }
+
+    /// Receives the data of the file `next_file` just announced into `into`,
+    /// asking the sender to go back over anything that arrives corrupted.
+    ///
+    /// Returns the number of bytes written to `into`, 0 if there's no file
+    /// waiting to be received.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `ConnectionAborted` if the sender cancels, or
+    /// of the kind of the last problem if `MAX_RETRIES` frames in a row are
+    /// missing or corrupt.
+    pub fn receive_file<W: io::Write>(&mut self, mut into: W) -> MResult<usize> {
+        if self.state != State::Data {
+            return Ok(0);
+        }
+        self.state = State::Header;
+
+        let mut buf = [0u8; MAX_SUBPACKET];
+        let mut offset: u32 = 0;
+        let mut retries = 0;
+
+        self.send_header(Header::with_position(ZRPOS, offset))?;
+        loop {
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ref e) if recoverable(e) && retries < MAX_RETRIES => {
+                    retries += 1;
+                    self.send_header(Header::with_position(ZRPOS, offset))?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            match header.kind {
+                ZDATA if header.position() == offset => {
+                    match self.read_data(&mut buf, &mut offset, &mut into) {
+                        Ok(()) => retries = 0,
+                        Err(ref e) if recoverable(e) && retries < MAX_RETRIES => {
+                            retries += 1;
+                            self.send_header(Header::with_position(ZRPOS, offset))?;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                ZEOF if header.position() == offset => return Ok(offset as usize),
+                // Data from before the sender rewound, its ZEOF, or it missed our
+                // ZRPOS and is still announcing the file. Tell it where we are, the
+                // rest of that frame gets skipped looking for the next header.
+                ZDATA | ZFILE => {
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return Err(invalid());
+                    }
+                    self.send_header(Header::with_position(ZRPOS, offset))?;
+                }
+                _ => {},
+            }
+        }
+    }
+
+    /// Reads the subpackets of a ZDATA frame into `into`, moving `offset` on
+    /// past each good one. Returns once the frame ends.
+    fn read_data<W: io::Write>(&mut self, buf: &mut [u8], offset: &mut u32, into: &mut W) -> MResult<()> {
+        loop {
+            let (len, end) = self.read_subpacket(buf)?;
+            into.write_all(&buf[..len])?;
+            *offset += len as u32;
+
+            match end {
+                ZCRCG => {},
+                ZCRCQ => self.send_header(Header::with_position(ZACK, *offset))?,
+                ZCRCW => {
+                    self.send_header(Header::with_position(ZACK, *offset))?;
+                    return Ok(());
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> MResult<u8> {
+        let mut byte = [0];
+        match self.inner.read_timeout(&mut byte, TIMEOUT)? {
+            0 => Err(ModemError::new(ErrorKind::UnexpectedEof)),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    /// Reads a byte, skipping the sender's flow control, which is never part of
+    /// the data since it's always escaped there
+    fn read_raw(&mut self) -> MResult<u8> {
+        loop {
+            match self.read_byte()? {
+                byte if byte & 0x7f == XON || byte & 0x7f == XOFF => continue,
+                byte => return Ok(byte),
+            }
+        }
+    }
+
+    /// Reads a byte of a binary header or subpacket, undoing ZDLE escaping
+    fn read_escaped(&mut self) -> MResult<Escaped> {
+        let byte = self.read_raw()?;
+        if byte != ZDLE {
+            return Ok(Escaped::Data(byte));
+        }
+
+        // ZDLE is also CAN, a run of them is the sender cancelling
+        let mut cans = 1;
+        loop {
+            match self.read_raw()? {
+                ZDLE => {
+                    cans += 1;
+                    if cans >= CANCEL_COUNT {
+                        return Err(ModemError::new(ErrorKind::ConnectionAborted));
+                    }
+                }
+                end @ ZCRCE..=ZCRCW => return Ok(Escaped::End(end)),
+                ZRUB0 => return Ok(Escaped::Data(0x7f)),
+                ZRUB1 => return Ok(Escaped::Data(0xff)),
+                byte if byte & 0x60 == 0x40 => return Ok(Escaped::Data(byte ^ 0x40)),
+                _ => return Err(invalid()),
+            }
+        }
+    }
+
+    fn read_escaped_byte(&mut self) -> MResult<u8> {
+        match self.read_escaped()? {
+            Escaped::Data(byte) => Ok(byte),
+            Escaped::End(_) => Err(invalid()),
+        }
+    }
+
+    /// Reads a subpacket into `buf`, returning its length and how it ended
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Interrupted` if the CRC doesn't match, or of
+    /// kind `InvalidData` if the subpacket doesn't fit in `buf`.
+    fn read_subpacket(&mut self, buf: &mut [u8]) -> MResult<(usize, u8)> {
+        let mut len = 0;
+        let end = loop {
+            match self.read_escaped()? {
+                Escaped::Data(byte) => {
+                    *buf.get_mut(len).ok_or_else(invalid)? = byte;
+                    len += 1;
+                }
+                Escaped::End(end) => break end,
+            }
+        };
+
+        // The CRC covers the end marker too
+        let valid = if self.crc32 {
+            let mut crc = [0u8; 4];
+            for byte in crc.iter_mut() {
+                *byte = self.read_escaped_byte()?;
+            }
+            u32::from_le_bytes(crc) == !crc32(crc32(!0, &buf[..len]), &[end])
+        } else {
+            let high = self.read_escaped_byte()?;
+            let low = self.read_escaped_byte()?;
+            u16::from_be_bytes([high, low]) == crc16(crc16(0, &buf[..len]), &[end])
+        };
+
+        if !valid {
+            return Err(ModemError::new(ErrorKind::Interrupted));
+        }
+        Ok((len, end))
+    }
+
+    /// Skips ahead to the next header and reads it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Interrupted` if the header's CRC doesn't
+    /// match, `InvalidData` if it's otherwise garbled, and `ConnectionAborted`
+    /// if the sender cancels while we're looking.
+    fn read_header(&mut self) -> MResult<Header> {
+        let mut cans = 0;
+        let format = loop {
+            let byte = self.read_raw()?;
+            cans = if byte == ZDLE { cans + 1 } else { 0 };
+            if cans >= CANCEL_COUNT {
+                return Err(ModemError::new(ErrorKind::ConnectionAborted));
+            }
+            if byte != ZPAD {
+                continue;
+            }
+
+            let mut byte = self.read_raw()?;
+            while byte == ZPAD {
+                byte = self.read_raw()?;
+            }
+            if byte != ZDLE {
+                continue;
+            }
+            match self.read_raw()? {
+                format @ (ZBIN | ZHEX | ZBIN32) => break format,
+                _ => continue,
+            }
+        };
+
+        let mut bytes = [0u8; 5];
+        let valid = match format {
+            ZHEX => {
+                for byte in bytes.iter_mut() {
+                    *byte = self.read_hex_byte()?;
+                }
+                let crc = [self.read_hex_byte()?, self.read_hex_byte()?];
+                u16::from_be_bytes(crc) == crc16(0, &bytes)
+            }
+            ZBIN => {
+                for byte in bytes.iter_mut() {
+                    *byte = self.read_escaped_byte()?;
+                }
+                let crc = [self.read_escaped_byte()?, self.read_escaped_byte()?];
+                self.crc32 = false;
+                u16::from_be_bytes(crc) == crc16(0, &bytes)
+            }
+            _ => {
+                for byte in bytes.iter_mut() {
+                    *byte = self.read_escaped_byte()?;
+                }
+                let mut crc = [0u8; 4];
+                for byte in crc.iter_mut() {
+                    *byte = self.read_escaped_byte()?;
+                }
+                self.crc32 = true;
+                u32::from_le_bytes(crc) == !crc32(!0, &bytes)
+            }
+        };
+
+        if !valid {
+            return Err(ModemError::new(ErrorKind::Interrupted));
+        }
+        Ok(Header { kind: bytes[0], data: [bytes[1], bytes[2], bytes[3], bytes[4]] })
+    }
+
+    fn read_hex_byte(&mut self) -> MResult<u8> {
+        let digit = |byte: u8| (byte as char).to_digit(16).map(|d| d as u8).ok_or_else(invalid);
+        let high = digit(self.read_raw()?)?;
+        let low = digit(self.read_raw()?)?;
+        Ok(high << 4 | low)
+    }
+
+    /// Sends `header` in hex, which never needs escaping
+    fn send_header(&mut self, header: Header) -> MResult<()> {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+
+        let mut bytes = [0u8; 7];
+        bytes[0] = header.kind;
+        bytes[1..5].copy_from_slice(&header.data);
+        let crc = crc16(0, &bytes[..5]);
+        bytes[5..].copy_from_slice(&crc.to_be_bytes());
+
+        self.inner.write_all(&[ZPAD, ZPAD, ZDLE, ZHEX])?;
+        for byte in bytes.iter() {
+            self.inner.write_all(&[HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]])?;
+        }
+        // CR, LF with the top bit set, then XON in case the sender's been paused
+        self.inner.write_all(&[b'\r', 0x8a])?;
+        if header.kind != ZFIN && header.kind != ZACK {
+            self.inner.write_all(&[XON])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::io::Write;
+    use crate::loopback::{self, End};
+
+    /// ZDLE-escape `byte` the way `sz` does by default
+    fn escape(out: &mut Vec<u8>, byte: u8) {
+        match byte & 0x7f {
+            ZDLE | 0x10 | XON | XOFF => out.extend_from_slice(&[ZDLE, byte ^ 0x40]),
+            _ => out.push(byte),
+        }
+    }
+
+    fn bin_header(header: Header, use_crc32: bool) -> Vec<u8> {
+        let mut bytes = vec![header.kind];
+        bytes.extend_from_slice(&header.data);
+        let crc = if use_crc32 {
+            (!crc32(!0, &bytes)).to_le_bytes().to_vec()
+        } else {
+            crc16(0, &bytes).to_be_bytes().to_vec()
+        };
+
+        let mut out = vec![ZPAD, ZDLE, if use_crc32 { ZBIN32 } else { ZBIN }];
+        for byte in bytes.iter().chain(&crc) {
+            escape(&mut out, *byte);
+        }
+        out
+    }
+
+    fn subpacket(data: &[u8], end: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        for byte in data {
+            escape(&mut out, *byte);
+        }
+        out.extend_from_slice(&[ZDLE, end]);
+        for byte in (!crc32(crc32(!0, data), &[end])).to_le_bytes().iter() {
+            escape(&mut out, *byte);
+        }
+        out
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(!crc32(!0, b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let header = Header::with_position(ZRPOS, 0x9011_9318);
+
+        // Hex, as we send them
+        let (a, b) = loopback::pair();
+        Receiver::new(a).send_header(header).unwrap();
+        let mut receiver = Receiver::new(b);
+        let read = receiver.read_header().unwrap();
+        assert_eq!((read.kind, read.position()), (ZRPOS, 0x9011_9318));
+
+        // Binary, with every byte of the position needing escaping
+        for &use_crc32 in &[false, true] {
+            let (mut a, b) = loopback::pair();
+            a.write_all(&bin_header(header, use_crc32)).unwrap();
+            let mut receiver = Receiver::new(b);
+            let read = receiver.read_header().unwrap();
+            assert_eq!((read.kind, read.position()), (ZRPOS, 0x9011_9318));
+            assert_eq!(receiver.crc32, use_crc32);
+        }
+    }
+
+    #[test]
+    fn corrupted_header() {
+        let mut bytes = bin_header(Header::with_flags(ZFILE, 0), true);
+        bytes[4] ^= 1;
+        let (mut a, b) = loopback::pair();
+        a.write_all(&bytes).unwrap();
+        assert_eq!(Receiver::new(b).read_header().err().unwrap().kind(), &ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn escape_decoding() {
+        let (mut a, b) = loopback::pair();
+        a.write_all(&[
+            ZDLE, 0x58, ZDLE, 0x51, ZDLE, 0xd3, ZDLE, ZRUB0, ZDLE, ZRUB1,
+            // Raw flow control is dropped
+            XON, b'a', XOFF | 0x80,
+            ZDLE, ZCRCW,
+            ZDLE, b'!',
+            ZDLE, ZDLE, ZDLE, ZDLE, ZDLE,
+        ]).unwrap();
+        let mut receiver = Receiver::new(b);
+
+        for &expected in &[ZDLE, XON, XOFF | 0x80, 0x7f, 0xff, b'a'] {
+            assert_eq!(receiver.read_escaped_byte().unwrap(), expected);
+        }
+        assert!(matches!(receiver.read_escaped().unwrap(), Escaped::End(ZCRCW)));
+        assert_eq!(receiver.read_escaped().err().unwrap().kind(), &ErrorKind::InvalidData);
+        assert_eq!(receiver.read_escaped().err().unwrap().kind(), &ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn corrupted_subpacket() {
+        let (mut a, b) = loopback::pair();
+        let mut packet = subpacket(b"hello", ZCRCE);
+        packet[1] ^= 1;
+        a.write_all(&packet).unwrap();
+        a.write_all(&subpacket(b"hello", ZCRCE)).unwrap();
+
+        let mut receiver = Receiver::new(b);
+        receiver.crc32 = true;
+        let mut buf = [0u8; 16];
+        assert_eq!(receiver.read_subpacket(&mut buf).unwrap_err().kind(), &ErrorKind::Interrupted);
+        assert_eq!(receiver.read_subpacket(&mut buf).unwrap(), (5, ZCRCE));
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    /// Just enough of `sz` to send one file, with the CRC of subpacket `corrupt` broken the first
+    /// time it goes. Returns every position the receiver asked it to send from.
+    fn send(mut wire: Receiver<End>, name: &str, data: &[u8], corrupt: usize) -> Vec<u32> {
+        const CHUNK: usize = 256;
+
+        let wait_for = |wire: &mut Receiver<End>, kind: u8| loop {
+            let header = wire.read_header().unwrap();
+            if header.kind == kind {
+                return header;
+            }
+        };
+
+        wait_for(&mut wire, ZRINIT);
+        let mut info = name.as_bytes().to_vec();
+        info.push(0);
+        info.extend_from_slice(format!("{} 0", data.len()).as_bytes());
+        info.push(0);
+        wire.inner.write_all(&bin_header(Header::with_flags(ZFILE, 0), true)).unwrap();
+        wire.inner.write_all(&subpacket(&info, ZCRCW)).unwrap();
+
+        let mut positions = Vec::new();
+        let mut corrupted = false;
+        loop {
+            let header = wire.read_header().unwrap();
+            match header.kind {
+                ZRPOS => {
+                    let position = header.position();
+                    positions.push(position);
+
+                    wire.inner.write_all(&bin_header(Header::with_position(ZDATA, position), true)).unwrap();
+                    let chunks: Vec<&[u8]> = data[position as usize..].chunks(CHUNK).collect();
+                    for (n, chunk) in chunks.iter().enumerate() {
+                        let end = if n + 1 == chunks.len() { ZCRCE } else { ZCRCG };
+                        let mut packet = subpacket(chunk, end);
+                        if position as usize / CHUNK + n == corrupt && !corrupted {
+                            *packet.last_mut().unwrap() ^= 0x01;
+                            corrupted = true;
+                        }
+                        wire.inner.write_all(&packet).unwrap();
+                    }
+                    wire.inner.write_all(&bin_header(Header::with_position(ZEOF, data.len() as u32), true)).unwrap();
+                },
+                // Only once something's been sent, the first ZRINIT may still be about
+                ZRINIT if !positions.is_empty() => break,
+                _ => {},
+            }
+        }
+
+        wire.inner.write_all(&bin_header(Header::with_position(ZFIN, 0), false)).unwrap();
+        wait_for(&mut wire, ZFIN);
+        wire.inner.write_all(b"OO").unwrap();
+        positions
+    }
+
+    #[test]
+    fn corrupted_data_is_resent() {
+        let (a, b) = loopback::pair();
+        let data = data(2000);
+
+        let to_send = data.clone();
+        let sending = thread::spawn(move || send(Receiver::new(b), "kernel8.img", &to_send, 3));
+
+        let mut receiver = Receiver::new(a);
+        let file = receiver.next_file().unwrap().unwrap();
+        assert_eq!(file.name(), "kernel8.img");
+        assert_eq!(file.size, Some(2000));
+
+        let mut received = Vec::new();
+        assert_eq!(receiver.receive_file(&mut received).unwrap(), 2000);
+        assert!(receiver.next_file().unwrap().is_none());
+
+        // Rewound to the start of the bad subpacket, everything before it was kept
+        assert_eq!(sending.join().unwrap(), [0, 3 * 256]);
+        assert_eq!(received, data);
+    }
+}
@@ -0,0 +1,112 @@
+// Builds the kernel symbol table that gets patched into the `.ksyms` section after linking.
+//
+//     rust-nm --demangle --defined-only --print-size kernel.elf | mksyms > ksyms.bin
+//
+// The makefile then links the kernel again with `.ksyms` made the size of ksyms.bin, which
+// only moves what comes after it, so none of the code addresses in the table change, and
+// objcopy swaps the table in. The layout has to match src/ksyms.rs, everything little endian:
+//
+//     magic: u32, count: u32, names_len: u32
+//     count * { address: u32, size: u32, name: u32 }   sorted by address
+//     names_len bytes of names, entry i's name runs from its `name` to entry i + 1's
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"KSYM");
+const HEADER_LEN: usize = 12;
+const ENTRY_LEN: usize = 12;
+
+struct Symbol {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+fn main() {
+    let mut symbols = Vec::new();
+    let mut has_section = false;
+
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap_or_else(|e| fail(&format!("reading stdin: {}", e)));
+        let (address, size, kind, name) = match parse_line(&line) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        match name {
+            "__ksyms_start" => has_section = true,
+            // Code only, and not the $x/$d mapping symbols
+            _ if matches!(kind, 'T' | 't' | 'W' | 'w') && !name.starts_with('$') => {
+                symbols.push(Symbol { address, size, name: strip_hash(name).to_string() })
+            },
+            _ => {},
+        }
+    }
+
+    if !has_section {
+        fail("no __ksyms_start, is the linker script up to date?");
+    }
+
+    symbols.sort_by_key(|symbol| symbol.address);
+    symbols.dedup_by_key(|symbol| symbol.address);
+
+    let table = build(&symbols);
+    io::stdout().write_all(&table).unwrap_or_else(|e| fail(&format!("writing stdout: {}", e)));
+}
+
+/// `address [size] type name`, size is missing for symbols without one
+fn parse_line(line: &str) -> Option<(u64, u64, char, &str)> {
+    let mut fields = line.splitn(3, ' ');
+    let address = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let second = fields.next()?;
+    let rest = fields.next()?;
+
+    if second.len() == 1 {
+        let kind = second.chars().next()?;
+        return Some((address, 0, kind, rest));
+    }
+
+    let size = u64::from_str_radix(second, 16).ok()?;
+    let (kind, name) = rest.split_once(' ')?;
+    Some((address, size, kind.chars().next()?, name))
+}
+
+/// Legacy mangling leaves a `::h<16 hex digits>` hash on the end that's no use to anyone
+fn strip_hash(name: &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((path, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => path,
+        _ => name,
+    }
+}
+
+fn build(symbols: &[Symbol]) -> Vec<u8> {
+    let names_len: usize = symbols.iter().map(|symbol| symbol.name.len()).sum();
+    let mut out = Vec::with_capacity(HEADER_LEN + symbols.len() * ENTRY_LEN + names_len);
+
+    out.extend_from_slice(&MAGIC.to_le_bytes());
+    out.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(names_len as u32).to_le_bytes());
+
+    let mut name = 0u32;
+    for symbol in symbols {
+        let address = u32::try_from(symbol.address)
+            .unwrap_or_else(|_| fail(&format!("{} is above 4GiB", symbol.name)));
+        out.extend_from_slice(&address.to_le_bytes());
+        out.extend_from_slice(&(symbol.size as u32).to_le_bytes());
+        out.extend_from_slice(&name.to_le_bytes());
+        name += symbol.name.len() as u32;
+    }
+
+    for symbol in symbols {
+        out.extend_from_slice(symbol.name.as_bytes());
+    }
+
+    out
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("mksyms: {}", message);
+    process::exit(1)
+}